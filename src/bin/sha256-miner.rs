@@ -1,35 +1,63 @@
-#![feature(decl_macro)]
-
 use anyhow::anyhow;
-use bytemuck::{cast_slice, cast_slice_mut};
+use bytemuck::{cast_slice, pod_read_unaligned};
+use serde::{Deserialize, Serialize};
+use sha2::digest::generic_array::GenericArray;
 use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::time::Instant;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
 use wgpu::wgt::PollType;
 use wgpu::{
-    include_spirv_raw, include_wgsl, Backends, BindGroup, BindGroupDescriptor,
-    BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer,
-    BufferBinding, BufferBindingType, BufferDescriptor, BufferUsages, ComputePipeline,
-    ComputePipelineDescriptor, Device, DeviceDescriptor, ExperimentalFeatures, Features, Instance,
-    InstanceDescriptor, MapMode, PipelineCompilationOptions, PipelineLayoutDescriptor,
-    Queue, ShaderModuleDescriptorPassthrough, ShaderStages,
+    Adapter, Backend, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferAsyncError, BufferBinding,
+    BufferBindingType, BufferDescriptor, BufferUsages, ComputePassDescriptor,
+    ComputePassTimestampWrites, ComputePipeline, ComputePipelineDescriptor, Device,
+    DeviceDescriptor, ErrorFilter, ExperimentalFeatures, Features, Limits, MapMode,
+    PipelineCompilationOptions, PipelineLayoutDescriptor, QuerySet, QuerySetDescriptor, QueryType,
+    Queue, ShaderModuleDescriptor, ShaderModuleDescriptorPassthrough, ShaderSource, ShaderStages,
+    SubmissionIndex, QUERY_SIZE,
 };
 
 /// Sha256 buffer type the shader uses.
 type FatSha256Buf = [u32; SHA256_BYTES];
 
 const SHA256_BYTES: usize = 32;
-const INPUT_SIZE: usize = 32;
-/// The shader treats `u32`s as `u8`s.
-const BLOCK_BUFFER_IN_SHADER: u64 = size_of::<FatSha256Buf>() as _;
+const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+const SHA1_IV: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+const SHA512_IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+/// Lanes of the Keccak-f[1600] state.
+const KECCAK_LANES: usize = 25;
+/// `Input` fields in front of the tail in the fat shader: midstate, prefix bit length, nonce
+/// offset and width, nonce count, and the fat target.
+const FAT_INPUT_HEADER_WORDS: usize = 8 + 2 + 2 + 1 + SHA256_BYTES;
+/// `Hits::count` and `Hits::overflow` in front of the hit array in the shader.
+const HITS_HEADER_WORDS: usize = 2;
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use num_format::{Locale, ToFormattedString};
-use wgpu_benchmarks::{default, set_up_logger};
+use wgpu_benchmarks::{
+    default, print_adapters, set_up_logger, write_report, AdapterArgs, OutputFormat,
+};
 
-#[derive(Parser, Debug)]
-#[command(about = "GPU Sha256 Miner Simulator")]
+#[derive(Parser, Debug, Clone)]
+#[command(about = "GPU hash miner simulator: SHA-256, SHA-1, SHA-512, SHA3-256 and Keccak-256")]
 struct Args {
     /// Number of threads per workgroup (WORKGROUP_SIZE)
     #[arg(long, default_value_t = 256)]
@@ -43,63 +71,990 @@ struct Args {
     #[arg(short, long, default_value_t = 64)]
     iterations: u32,
 
-    /// Target difficulty in bits
+    /// Hash function to mine.
+    #[arg(long, value_enum, default_value_t = Algorithm::Sha256)]
+    algo: Algorithm,
+
+    /// Target difficulty in bits: a shorthand for a target whose first N bits are zero
     #[arg(short, long, default_value_t = 32)]
     difficulty: u32,
 
-    /// The start hex data (in hex string).
+    /// Hits are hashes at most this target, big-endian and as long as the digest (64 hex digits
+    /// for SHA-256).
+    #[arg(long, conflicts_with_all = ["difficulty", "target_difficulty"])]
+    target: Option<String>,
+
+    /// Target as a (fractional) difficulty. Difficulty 1 is Bitcoin's, a target of
+    /// 0xffff * 2^208, or about 2^32 hashes per hit, whatever the digest length.
+    #[arg(long, conflicts_with = "difficulty")]
+    target_difficulty: Option<f64>,
+
+    /// Message to mine (in hex string). Defaults to 32 zero bytes.
+    #[arg(long)]
+    message: Option<String>,
+
+    /// Position of the nonce field in the message, in bytes.
+    #[arg(long, default_value_t = 0)]
+    nonce_offset: usize,
+
+    /// Width of the nonce field in bytes. Defaults to the rest of the message.
+    #[arg(long)]
+    nonce_width: Option<usize>,
+
+    /// Start value of the nonce field (in hex string, little-endian). Defaults to the field's
+    /// bytes in the message.
     #[arg(long)]
     start: Option<String>,
+
+    /// Search up to this value of the nonce field, exclusive (in hex string, little-endian), and
+    /// exit with an error if there's no hit. Defaults to searching the whole field.
+    #[arg(long, conflicts_with_all = ["count", "coinbase"])]
+    end: Option<String>,
+
+    /// Search this many nonces from `--start`, like `--end`.
+    #[arg(long, conflicts_with = "coinbase")]
+    count: Option<u64>,
+
+    /// How the kernel's buffers hold the message, target and hashes.
+    #[arg(long, value_enum, default_value_t = Layout::Packed)]
+    layout: Layout,
+
+    /// Measure the hashrate of both layouts over this many dispatches, check their hits on the
+    /// first dispatch against the CPU, print and exit.
+    #[arg(long, value_name = "DISPATCHES", conflicts_with = "pipeline_benchmark")]
+    compare_layouts: Option<usize>,
+
+    /// Where the kernel comes from. `spirv` and `dxil` are precompiled kernels passed through to
+    /// the driver; they need the Vulkan or DX12 backend respectively.
+    #[arg(long, value_enum, default_value_t = KernelSource::Wgsl)]
+    shader_source: KernelSource,
+
+    /// Precompiled kernel for `--shader-source spirv|dxil`. Defaults to `shader.spv` or
+    /// `shader.dxil`.
+    #[arg(long)]
+    shader_file: Option<PathBuf>,
+
+    /// Where to mine. `cpu` runs the reference implementation over the same nonce space.
+    #[arg(long, value_enum, default_value_t = MinerDevice::Gpu)]
+    device: MinerDevice,
+
+    /// Worker threads for `--device cpu` and `--verify`. Defaults to the number of logical CPUs.
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Re-check every GPU hit on the CPU: its hash must meet the target and it must lie in
+    /// the nonce range of the dispatch that returned it.
+    #[arg(long)]
+    verify: bool,
+
+    /// Dispatches kept in flight, each with its own buffers. 1 waits for every readback before
+    /// queuing the next dispatch.
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+    in_flight: u32,
+
+    /// Measure the hashrate over this many dispatches, first synchronously and then with
+    /// `--in-flight` dispatches queued, print both and exit.
+    #[arg(long, value_name = "DISPATCHES")]
+    pipeline_benchmark: Option<usize>,
+
+    /// Capacity of the GPU hit buffer. Hits beyond it are counted but dropped.
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..))]
+    max_hits: u32,
+
+    /// Mine at a target no hash meets for `--warmup-secs` and then `--duration` seconds, print
+    /// the hashrate and the GPU and host time per dispatch as JSON and exit.
+    #[arg(long, conflicts_with_all = ["pipeline_benchmark", "compare_layouts", "tune"])]
+    benchmark: bool,
+
+    /// Seconds `--benchmark` measures for, warmup excluded.
+    #[arg(long, default_value_t = 10.0)]
+    duration: f64,
+
+    /// Seconds at the start of `--benchmark` excluded from the measurement.
+    #[arg(long, default_value_t = 1.0)]
+    warmup_secs: f64,
+
+    /// Write the `--benchmark` report to this file instead of stdout.
+    #[arg(long)]
+    benchmark_output: Option<PathBuf>,
+
+    /// Benchmark a grid of `--workgroup-size`, `--dispatch-x` and `--iterations` values within
+    /// the adapter's limits, print them ranked, save the fastest to `--profile` and exit.
+    #[arg(long, conflicts_with_all = ["pipeline_benchmark", "compare_layouts"])]
+    tune: bool,
+
+    /// Seconds `--tune` benchmarks each configuration for.
+    #[arg(long, default_value_t = 1.0, value_parser = parse_secs)]
+    tune_duration: f64,
+
+    /// Kernel parameters per adapter and backend, as saved by `--tune` (to
+    /// `sha256-miner-profiles.json` by default). Otherwise the adapter's entry replaces
+    /// `--workgroup-size`, `--dispatch-x`, `--iterations` and `--layout`.
+    #[arg(long)]
+    profile: Option<PathBuf>,
+
+    /// Mine on several adapters at once: `all`, or a comma separated list of indices or name
+    /// substrings from `--list-adapters`. Each takes an interleaved share of the dispatches.
+    #[arg(long, conflicts_with = "adapter")]
+    adapters: Option<String>,
+
+    /// Save the search progress to this file every `--checkpoint-interval` seconds. Defaults to
+    /// the `--resume` file.
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// Seconds between checkpoints.
+    #[arg(long, default_value_t = 60)]
+    checkpoint_interval: u64,
+
+    /// Continue the search saved in this checkpoint. The message, target and kernel parameters
+    /// must be the same as when it was saved.
+    #[arg(long)]
+    resume: Option<PathBuf>,
+
+    #[command(flatten)]
+    bitcoin: BitcoinArgs,
+
+    #[command(flatten)]
+    adapter: AdapterArgs,
+}
+
+/// Bitcoin block header mining: double SHA-256 over the 80-byte header, searching its 32-bit
+/// nonce against the target decoded from nBits, unless `--target` or `--target-difficulty`
+/// overrides it.
+#[derive(clap::Args, Debug, Clone)]
+#[command(next_help_heading = "Bitcoin header mining")]
+struct BitcoinArgs {
+    /// Block header to mine, 80 bytes in hex as serialized.
+    #[arg(long, conflicts_with_all = ["message", "nonce_offset", "nonce_width", "difficulty", "block_version"])]
+    header: Option<String>,
+
+    /// Block version. Together with the other block fields, builds the header to mine.
+    #[arg(
+        long,
+        requires_all = ["prev_block", "block_time", "bits"],
+        conflicts_with_all = ["message", "nonce_offset", "nonce_width", "difficulty"]
+    )]
+    block_version: Option<i32>,
+
+    /// Previous block hash, in the usual (byte reversed) hex.
+    #[arg(long, requires = "block_version")]
+    prev_block: Option<String>,
+
+    /// Merkle root, in the usual (byte reversed) hex. Computed from `--coinbase` if given.
+    #[arg(long, requires = "block_version", conflicts_with = "coinbase")]
+    merkle_root: Option<String>,
+
+    /// Block timestamp in Unix seconds.
+    #[arg(long, requires = "block_version")]
+    block_time: Option<u32>,
+
+    /// Compact target (nBits) in hex, e.g. `1d00ffff`.
+    #[arg(long, requires = "block_version")]
+    bits: Option<String>,
+
+    /// Coinbase transaction in hex. The merkle root is computed from it and `--merkle-branch`,
+    /// and its extranonce is incremented whenever the 2^32 nonces are exhausted.
+    #[arg(long, requires = "extranonce_offset")]
+    coinbase: Option<String>,
+
+    /// Position of the extranonce in the coinbase, in bytes.
+    #[arg(long, requires = "coinbase")]
+    extranonce_offset: Option<usize>,
+
+    /// Width of the extranonce in bytes. It's incremented as a little-endian integer.
+    #[arg(long, default_value_t = 4, requires = "coinbase")]
+    extranonce_width: usize,
+
+    /// Merkle branch of the coinbase: comma separated hashes in internal byte order, as stratum
+    /// sends them.
+    #[arg(long, value_delimiter = ',', requires = "coinbase")]
+    merkle_branch: Vec<String>,
+}
+
+impl BitcoinArgs {
+    fn enabled(&self) -> bool {
+        self.header.is_some() || self.block_version.is_some()
+    }
+}
+
+/// Parses a positive, finite number of seconds.
+fn parse_secs(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+        Ok(_) => Err("must be a positive number of seconds".into()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq)]
+enum MinerDevice {
+    Gpu,
+    Cpu,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq)]
+enum KernelSource {
+    /// `hash-miner.wgsl` with the `--algo`'s `hash-miner-*.wgsl`, or `sha256-miner.wgsl` for
+    /// `--layout fat`; works on every backend.
+    Wgsl,
+    Spirv,
+    Dxil,
+}
+
+impl KernelSource {
+    /// The backend a passthrough kernel needs, `None` for WGSL.
+    fn passthrough_backend(self) -> Option<Backend> {
+        match self {
+            KernelSource::Wgsl => None,
+            KernelSource::Spirv => Some(Backend::Vulkan),
+            KernelSource::Dxil => Some(Backend::Dx12),
+        }
+    }
+
+    fn default_file(self) -> &'static str {
+        match self {
+            KernelSource::Wgsl => "",
+            KernelSource::Spirv => "shader.spv",
+            KernelSource::Dxil => "shader.dxil",
+        }
+    }
+}
+
+/// How the kernel's buffers hold bytes.
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Layout {
+    /// 32-bit words in the byte order the hash function reads them, big-endian for SHA-256
+    /// (`hash-miner.wgsl`).
+    Packed,
+    /// One byte per `u32` (`sha256-miner.wgsl`). SHA-256 only.
+    Fat,
+}
+
+impl Layout {
+    /// The kernel's `Input` for `work`.
+    fn input_words(self, work: &Work) -> Vec<u32> {
+        match self {
+            Layout::Packed => work.message.packed_input_words(work),
+            Layout::Fat => work.message.fat_input_words(work),
+        }
+    }
+
+    /// Words per `Hit`: the nonce offset, then the hash.
+    fn hit_words(self, algorithm: Algorithm) -> usize {
+        match self {
+            Layout::Packed => 1 + algorithm.digest_bytes() / size_of::<u32>(),
+            Layout::Fat => 1 + SHA256_BYTES,
+        }
+    }
+
+    fn decode_hash(self, words: &[u32]) -> Vec<u8> {
+        match self {
+            Layout::Packed => words.iter().flat_map(|x| x.to_be_bytes()).collect(),
+            Layout::Fat => convert_fat_buf(words.try_into().unwrap()).to_vec(),
+        }
+    }
+}
+
+/// Hash functions the miner supports. Each plugs its compression function into
+/// `hash-miner.wgsl`; SHA-256 also has the fat layout and Bitcoin's double hashing.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Algorithm {
+    #[default]
+    Sha256,
+    Sha1,
+    Sha512,
+    #[value(name = "sha3-256")]
+    #[serde(rename = "sha3-256")]
+    Sha3_256,
+    /// Keccak-256 as Ethereum uses it: SHA3-256 with the original Keccak padding.
+    Keccak256,
+}
+
+impl Algorithm {
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::Sha3_256 => "sha3-256",
+            Algorithm::Keccak256 => "keccak256",
+        }
+    }
+
+    fn digest_bytes(self) -> usize {
+        match self {
+            Algorithm::Sha1 => 20,
+            Algorithm::Sha256 | Algorithm::Sha3_256 | Algorithm::Keccak256 => 32,
+            Algorithm::Sha512 => 64,
+        }
+    }
+
+    /// Bytes the compression function takes at a time; the sponge's rate for Keccak.
+    fn block_bytes(self) -> usize {
+        match self {
+            Algorithm::Sha256 | Algorithm::Sha1 => 64,
+            Algorithm::Sha512 => 128,
+            Algorithm::Sha3_256 | Algorithm::Keccak256 => 136,
+        }
+    }
+
+    /// Whether the kernel reads the message as big-endian words. Keccak's lanes are
+    /// little-endian.
+    fn big_endian(self) -> bool {
+        !matches!(self, Algorithm::Sha3_256 | Algorithm::Keccak256)
+    }
+
+    fn wgsl(self, layout: Layout) -> Cow<'static, str> {
+        if layout == Layout::Fat {
+            return include_str!("../sha256-miner.wgsl").into();
+        }
+        let hash = match self {
+            Algorithm::Sha256 => include_str!("../hash-miner-sha256.wgsl"),
+            Algorithm::Sha1 => include_str!("../hash-miner-sha1.wgsl"),
+            Algorithm::Sha512 => include_str!("../hash-miner-sha512.wgsl"),
+            Algorithm::Sha3_256 | Algorithm::Keccak256 => include_str!("../hash-miner-keccak.wgsl"),
+        };
+        [hash, include_str!("../hash-miner.wgsl")].concat().into()
+    }
+
+    /// The state after `prefix`, a whole number of blocks, as the kernel's `Input::midstate`
+    /// holds it. 64-bit words are split into halves in the order the kernel's words are.
+    fn midstate(self, prefix: &[u8]) -> Vec<u32> {
+        let blocks = prefix.chunks_exact(self.block_bytes());
+        match self {
+            Algorithm::Sha256 => {
+                let mut state = SHA256_IV;
+                let blocks = blocks
+                    .map(|x| *GenericArray::from_slice(x))
+                    .collect::<Vec<_>>();
+                sha2::compress256(&mut state, &blocks);
+                state.to_vec()
+            }
+            Algorithm::Sha1 => {
+                let mut state = SHA1_IV;
+                let blocks = blocks
+                    .map(|x| *GenericArray::from_slice(x))
+                    .collect::<Vec<_>>();
+                sha1::compress(&mut state, &blocks);
+                state.to_vec()
+            }
+            Algorithm::Sha512 => {
+                let mut state = SHA512_IV;
+                let blocks = blocks
+                    .map(|x| *GenericArray::from_slice(x))
+                    .collect::<Vec<_>>();
+                sha2::compress512(&mut state, &blocks);
+                state
+                    .iter()
+                    .flat_map(|&x| [(x >> 32) as u32, x as u32])
+                    .collect()
+            }
+            Algorithm::Sha3_256 | Algorithm::Keccak256 => {
+                let mut lanes = [0_u64; KECCAK_LANES];
+                for block in blocks {
+                    for (lane, bytes) in lanes.iter_mut().zip(block.chunks_exact(size_of::<u64>()))
+                    {
+                        *lane ^= u64::from_le_bytes(bytes.try_into().unwrap());
+                    }
+                    keccak::f1600(&mut lanes);
+                }
+                lanes
+                    .iter()
+                    .flat_map(|&x| [x as u32, (x >> 32) as u32])
+                    .collect()
+            }
+        }
+    }
+
+    /// Pads the end of a `message_len`-byte message to whole blocks.
+    fn pad(self, tail: &mut Vec<u8>, message_len: usize) {
+        let bit_len = message_len as u128 * 8;
+        match self {
+            Algorithm::Sha256 | Algorithm::Sha1 | Algorithm::Sha512 => {
+                // The length is 64 bits, or 128 for SHA-512.
+                let length_bytes = self.block_bytes() / 8;
+                tail.push(0x80);
+                let padded_len = (tail.len() + length_bytes).next_multiple_of(self.block_bytes());
+                tail.resize(padded_len - length_bytes, 0);
+                tail.extend(&bit_len.to_be_bytes()[size_of::<u128>() - length_bytes..]);
+            }
+            Algorithm::Sha3_256 | Algorithm::Keccak256 => {
+                tail.push(if self == Algorithm::Sha3_256 {
+                    0x06
+                } else {
+                    0x01
+                });
+                tail.resize(tail.len().next_multiple_of(self.block_bytes()), 0);
+                *tail.last_mut().unwrap() |= 0x80;
+            }
+        }
+    }
 }
 
 struct State {
     device: Device,
     queue: Queue,
     pipeline: ComputePipeline,
+    /// One per dispatch that can be in flight.
+    slots: Vec<Slot>,
+    layout: Layout,
+    /// Words per `Hit` in `Hits`.
+    hit_words: usize,
+    dispatch_x: u32,
+    max_hits: usize,
+    /// Size of `Hits`, which the timestamps follow in `Slot::map_read_buffer`.
+    result_size: u64,
+    /// Nanoseconds per timestamp tick, if the compute passes are timed.
+    timestamp_period: Option<f32>,
+}
+
+/// Buffers of one in-flight dispatch.
+struct Slot {
     input_buffer: Buffer,
     result_buffer: Buffer,
     map_read_buffer: Buffer,
     bind_group: BindGroup,
+    /// Timestamps of the start and end of the compute pass, and where they're resolved to.
+    timestamps: Option<(QuerySet, Buffer)>,
+}
+
+/// One dispatch worth of nonces: `start + [0, count)` in `message`.
+#[derive(Clone)]
+struct Work {
+    /// Position in the order `WorkSource` hands out dispatches.
+    index: u64,
+    message: Arc<Message>,
+    start: Vec<u8>,
+    /// `hashes_per_dispatch`, or less for the last dispatch of a nonce space.
+    count: u32,
+    target: Target,
+}
+
+/// Hands out the nonce space one dispatch at a time, in order. Shared by all devices.
+struct WorkSource {
+    message: Arc<Message>,
+    target: Target,
+    next: Vec<u8>,
+    hashes_per_dispatch: u32,
+    /// Nonces handed out for `message`.
+    used: u64,
+    /// Nonces to search in each message: the `--end` or `--count` range, or the nonce field's
+    /// space if it's small enough to run out.
+    space: Option<u64>,
+    /// Rolled to get a fresh message when the nonce space runs out.
+    coinbase: Option<bitcoin::Coinbase>,
+    /// Dispatches handed out.
+    dispatches: u64,
+    /// The nonce field and extranonce of the first dispatch.
+    first_nonce: Vec<u8>,
+    first_extranonce: Option<Vec<u8>>,
+}
+
+impl WorkSource {
+    fn new(
+        message: Message,
+        target: Target,
+        hashes_per_dispatch: u32,
+        coinbase: Option<bitcoin::Coinbase>,
+        range: Option<u64>,
+    ) -> Self {
+        let width = message.nonce_width;
+        let field = (width < size_of::<u64>()).then(|| 1_u64 << (width * 8));
+        Self {
+            next: message.nonce().to_vec(),
+            first_nonce: message.nonce().to_vec(),
+            first_extranonce: coinbase.as_ref().map(|x| x.extranonce().to_vec()),
+            message: Arc::new(message),
+            target,
+            hashes_per_dispatch,
+            used: 0,
+            space: range.into_iter().chain(field).min(),
+            coinbase,
+            dispatches: 0,
+        }
+    }
+
+    /// Continues a fresh source after its first `dispatches` dispatches, as if they had been
+    /// handed out.
+    fn skip(&mut self, dispatches: u64) {
+        let hashes_per_dispatch = self.hashes_per_dispatch as u64;
+        let mut remaining = dispatches;
+        if let Some(space) = self.space
+            && let Some(coinbase) = &mut self.coinbase
+        {
+            let per_space = space.div_ceil(hashes_per_dispatch);
+            if remaining >= per_space {
+                for _ in 0..remaining / per_space {
+                    coinbase.roll();
+                }
+                self.message = Arc::new(coinbase.apply(&self.message));
+                self.next = self.message.nonce().to_vec();
+                remaining %= per_space;
+            }
+        }
+        for _ in 0..remaining {
+            add_big_int(&mut self.next, hashes_per_dispatch);
+        }
+        self.used += remaining * hashes_per_dispatch;
+        self.dispatches += dispatches;
+    }
+
+    /// Where dispatch `dispatches` starts: the nonce field, and the extranonce if it's rolled.
+    /// Past the end of a bounded search, the end of its range.
+    fn position(&self, dispatches: u64) -> (Vec<u8>, Option<Vec<u8>>) {
+        let hashes_per_dispatch = self.hashes_per_dispatch as u64;
+        let mut nonce = self.first_nonce.clone();
+        let mut extranonce = self.first_extranonce.clone();
+        let hashes = match self.space {
+            Some(space) => {
+                let mut remaining = dispatches;
+                if let Some(extranonce) = &mut extranonce {
+                    let per_space = space.div_ceil(hashes_per_dispatch);
+                    add_big_int(extranonce, remaining / per_space);
+                    remaining %= per_space;
+                }
+                (remaining * hashes_per_dispatch).min(space)
+            }
+            None => dispatches * hashes_per_dispatch,
+        };
+        add_big_int(&mut nonce, hashes);
+        (nonce, extranonce)
+    }
+
+    /// Whether `next` has run out of dispatches.
+    fn exhausted(&self) -> bool {
+        self.coinbase.is_none() && self.space.is_some_and(|space| self.used >= space)
+    }
+
+    /// The next dispatch, or `None` once the nonce space is exhausted. The last dispatch of a
+    /// nonce space is cut short so it doesn't search past its end.
+    fn next(&mut self) -> Option<Work> {
+        if let Some(space) = self.space
+            && self.used >= space
+        {
+            let coinbase = self.coinbase.as_mut()?;
+            coinbase.roll();
+            self.message = Arc::new(coinbase.apply(&self.message));
+            self.next = self.message.nonce().to_vec();
+            self.used = 0;
+            eprintln!(
+                "Nonce space exhausted; extranonce rolled to {}",
+                hex::encode(coinbase.extranonce())
+            );
+        }
+        let count = match self.space {
+            Some(space) => (space - self.used).min(self.hashes_per_dispatch as u64) as u32,
+            None => self.hashes_per_dispatch,
+        };
+        let work = Work {
+            index: self.dispatches,
+            message: self.message.clone(),
+            start: self.next.clone(),
+            count,
+            target: self.target.clone(),
+        };
+        add_big_int(&mut self.next, count as u64);
+        self.used += count as u64;
+        self.dispatches += 1;
+        Some(work)
+    }
+}
+
+/// How candidates are hashed and compared to the target. These are compiled into the kernel.
+#[derive(Debug, Clone, Copy)]
+struct Scheme {
+    algorithm: Algorithm,
+    /// SHA-256 only.
+    double_sha256: bool,
+    /// Read the hash as a little-endian integer, as Bitcoin does.
+    little_endian: bool,
+}
+
+impl Scheme {
+    /// What the hashes are called in the output.
+    fn name(&self) -> &'static str {
+        if self.double_sha256 {
+            "sha256d"
+        } else {
+            self.algorithm.name()
+        }
+    }
+
+    fn hash(&self, message: &[u8]) -> Vec<u8> {
+        let hash = cpu::hash(self.algorithm, message);
+        if self.double_sha256 {
+            cpu::hash(self.algorithm, &hash)
+        } else {
+            hash
+        }
+    }
+
+    fn is_hit(&self, hash: &[u8], target: &Target) -> bool {
+        if self.little_endian {
+            hash.iter().rev().le(target.0.iter())
+        } else {
+            hash.iter().le(target.0.iter())
+        }
+    }
+}
+
+/// A hash is a hit if it's at most this value, big-endian and as long as the digest. Uploaded
+/// with every dispatch, so changing it doesn't rebuild the pipeline.
+#[derive(Debug, Clone)]
+struct Target(Vec<u8>);
+
+impl Target {
+    /// A target no hash of `bytes` bytes meets, barring a zero hash.
+    fn zero(bytes: usize) -> Self {
+        Self(vec![0; bytes])
+    }
+
+    /// The largest `bytes`-byte target with `bits` leading zero bits.
+    fn leading_zeros(bits: u32, bytes: usize) -> anyhow::Result<Self> {
+        if bits > bytes as u32 * 8 {
+            return Err(anyhow!("`--difficulty` must be <= {}", bytes * 8));
+        }
+        let mut target = vec![0xff_u8; bytes];
+        let full_bytes = (bits / 8) as usize;
+        target[..full_bytes].fill(0);
+        if !bits.is_multiple_of(8) {
+            target[full_bytes] = 0xff >> (bits % 8);
+        }
+        Ok(Self(target))
+    }
+
+    fn from_hex(hex: &str, bytes: usize) -> anyhow::Result<Self> {
+        let target = hex::decode(hex)?;
+        if target.len() != bytes {
+            return Err(anyhow!("`--target` must be {} bytes", bytes));
+        }
+        Ok(Self(target))
+    }
+
+    /// `0xffff * 2^208 / difficulty`, Bitcoin's definition of difficulty, as a `bytes`-byte
+    /// target. Other digest lengths get the same share of their hashes as hits: the 256-bit
+    /// target is cut short, or extended with ones.
+    fn from_difficulty(difficulty: f64, bytes: usize) -> anyhow::Result<Self> {
+        if !(difficulty.is_finite() && difficulty > 0.0) {
+            return Err(anyhow!("`--target-difficulty` must be positive"));
+        }
+        let mut value = 65535.0 * 2_f64.powi(208) / difficulty;
+        let mut target = [0_u8; SHA256_BYTES];
+        if value >= 2_f64.powi(256) {
+            return Ok(Self(vec![0xff; bytes]));
+        }
+        // Peel off bytes from the most significant one; every step is exact.
+        for (i, byte) in target.iter_mut().enumerate() {
+            let scale = 2_f64.powi(8 * (SHA256_BYTES - 1 - i) as i32);
+            let digit = (value / scale).floor().min(255.0);
+            *byte = digit as u8;
+            value -= digit * scale;
+        }
+        let mut target = target.to_vec();
+        target.resize(bytes, 0xff);
+        Ok(Self(target))
+    }
+}
+
+/// Progress of a search, saved by `--checkpoint` and continued by `--resume`.
+#[derive(Serialize, Deserialize, Debug)]
+struct Checkpoint {
+    params: SearchParams,
+    /// Dispatches searched, counted from the start of the search. Dispatches finished out of
+    /// order beyond these are searched again on resume.
+    dispatches: u64,
+    /// Value of the nonce field the search continues from (hex, little-endian), at the end of
+    /// the range once it's all searched.
+    next_nonce: String,
+    /// The coinbase extranonce `next_nonce` belongs to (hex), when it's rolled.
+    extranonce: Option<String>,
+    hashes: u64,
+    elapsed_secs: f64,
+}
+
+impl Checkpoint {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let file =
+            File::open(path).map_err(|e| anyhow!("Reading {} failed: {}", path.display(), e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| anyhow!("{} isn't a checkpoint: {}", path.display(), e))
+    }
+
+    /// Writes through a temporary file, so being killed mid-write keeps the previous checkpoint.
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Fails, naming the differences, if the checkpoint was saved by a different search.
+    fn check_params(&self, params: &SearchParams) -> anyhow::Result<()> {
+        let saved = serde_json::to_value(&self.params)?;
+        let current = serde_json::to_value(params)?;
+        let differences = saved
+            .as_object()
+            .unwrap()
+            .iter()
+            .filter(|(key, value)| current.get(key.as_str()) != Some(*value))
+            .map(|(key, value)| {
+                format!("{}: {} in the checkpoint, {} now", key, value, current[key])
+            })
+            .collect::<Vec<_>>();
+        if !differences.is_empty() {
+            return Err(anyhow!(
+                "The checkpoint is for a different search:\n  {}",
+                differences.join("\n  ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Everything that decides which nonces the n-th dispatch searches and what a hit is.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct SearchParams {
+    /// Hex, with the nonce field at its start value.
+    message: String,
+    nonce_offset: usize,
+    nonce_width: usize,
+    /// Offset and width of the coinbase extranonce that's rolled.
+    extranonce: Option<(usize, usize)>,
+    /// Nonces in the `--end` or `--count` range.
+    range: Option<u64>,
+    /// Hex.
+    target: String,
+    /// Checkpoints from before `--algo` are SHA-256.
+    #[serde(default)]
+    algorithm: Algorithm,
+    double_sha256: bool,
+    little_endian: bool,
+    workgroup_size: u32,
+    dispatch_x: u32,
+    iterations: u32,
+}
+
+impl SearchParams {
+    fn new(
+        args: &Args,
+        message: &Message,
+        coinbase: Option<&bitcoin::Coinbase>,
+        range: Option<u64>,
+        target: &Target,
+        scheme: &Scheme,
+    ) -> Self {
+        Self {
+            message: hex::encode(&message.bytes),
+            nonce_offset: message.nonce_offset,
+            nonce_width: message.nonce_width,
+            extranonce: coinbase.map(|x| x.extranonce_field()),
+            range,
+            target: hex::encode(&target.0),
+            algorithm: scheme.algorithm,
+            double_sha256: scheme.double_sha256,
+            little_endian: scheme.little_endian,
+            workgroup_size: args.workgroup_size,
+            dispatch_x: args.dispatch_x,
+            iterations: args.iterations,
+        }
+    }
+}
+
+/// The fastest kernel parameters `--tune` found for an adapter.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct Profile {
+    workgroup_size: u32,
+    dispatch_x: u32,
+    iterations: u32,
+    layout: Layout,
+    /// What `--tune` measured.
+    hashrate: f64,
+}
+
+impl Profile {
+    /// `--profile` entries are keyed by this.
+    fn key(adapter: &Adapter) -> String {
+        let info = adapter.get_info();
+        format!("{} ({})", info.name, info.backend)
+    }
+
+    fn load_all(path: &Path) -> anyhow::Result<BTreeMap<String, Profile>> {
+        let file =
+            File::open(path).map_err(|e| anyhow!("Reading {} failed: {}", path.display(), e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| anyhow!("{} isn't a profile file: {}", path.display(), e))
+    }
+
+    fn apply(&self, args: &mut Args) {
+        args.workgroup_size = self.workgroup_size;
+        args.dispatch_x = self.dispatch_x;
+        args.iterations = self.iterations;
+        args.layout = self.layout;
+    }
+}
+
+/// A submitted dispatch whose result is being mapped.
+struct InFlight {
+    slot: usize,
+    work: Work,
+    submission: SubmissionIndex,
+    mapped: oneshot::Receiver<Result<(), BufferAsyncError>>,
+    /// Time spent submitting.
+    host_time: Duration,
+}
+
+/// An input whose hash meets the target.
+#[derive(Debug, Clone, PartialEq)]
+struct Hit {
+    /// Nonce offset from the start of the dispatch.
+    offset: u32,
+    nonce: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+struct SearchResult {
+    /// Ordered by `offset`.
+    hits: Vec<Hit>,
+    /// All hits found, including the ones dropped on overflow.
+    found: u32,
+    overflow: bool,
+    /// Duration of the compute pass, if it's timed.
+    gpu_time: Option<Duration>,
+    /// Time the host spent submitting the dispatch and reading back its result, waiting
+    /// excluded.
+    host_time: Duration,
+}
+
+impl SearchResult {
+    fn new(mut hits: Vec<Hit>, found: u32, overflow: bool) -> Self {
+        hits.sort_by_key(|x| x.offset);
+        Self {
+            hits,
+            found,
+            overflow,
+            gpu_time: None,
+            host_time: Duration::ZERO,
+        }
+    }
 }
 
 impl State {
-    async fn new(args: &Args) -> anyhow::Result<Self> {
-        let instance = Instance::new(&InstanceDescriptor {
-            backends: Backends::from_env().unwrap_or_default(),
+    async fn new(
+        args: &Args,
+        adapter: &Adapter,
+        message: &Message,
+        scheme: &Scheme,
+        layout: Layout,
+    ) -> anyhow::Result<Self> {
+        let passthrough = args.shader_source.passthrough_backend();
+        if let Some(backend) = passthrough {
+            let info = adapter.get_info();
+            if info.backend != backend {
+                return Err(anyhow!(
+                    "`--shader-source {:?}` needs the {} backend, but the adapter uses {}",
+                    args.shader_source,
+                    backend,
+                    info.backend
+                ));
+            }
+            if !adapter
+                .features()
+                .contains(Features::EXPERIMENTAL_PASSTHROUGH_SHADERS)
+            {
+                return Err(anyhow!("The adapter doesn't support passthrough shaders"));
+            }
+        }
+
+        let mut timestamps = args.benchmark;
+        if timestamps && !adapter.features().contains(Features::TIMESTAMP_QUERY) {
+            log::warn!("The adapter doesn't support TIMESTAMP_QUERY; GPU time isn't measured");
+            timestamps = false;
+        }
+        let mut required_features = Features::empty();
+        if timestamps {
+            required_features |= Features::TIMESTAMP_QUERY;
+        }
+        // The defaults cap workgroups at 256 invocations; ask for what the adapter can do, so the
+        // kernel parameters are checked against its real limits.
+        let adapter_limits = adapter.limits();
+        let required_limits = Limits {
+            max_compute_workgroup_size_x: adapter_limits.max_compute_workgroup_size_x,
+            max_compute_invocations_per_workgroup: adapter_limits
+                .max_compute_invocations_per_workgroup,
+            max_compute_workgroups_per_dimension: adapter_limits
+                .max_compute_workgroups_per_dimension,
             ..default!()
-        });
-        let adapter = instance.request_adapter(&default!()).await?;
+        };
         let (device, queue) = adapter
-            .request_device(&DeviceDescriptor {
-                required_features: Features::EXPERIMENTAL_PASSTHROUGH_SHADERS,
-                experimental_features: unsafe { ExperimentalFeatures::enabled() },
-                ..default!()
+            .request_device(&match passthrough {
+                Some(_) => DeviceDescriptor {
+                    required_features: required_features
+                        | Features::EXPERIMENTAL_PASSTHROUGH_SHADERS,
+                    required_limits,
+                    experimental_features: unsafe { ExperimentalFeatures::enabled() },
+                    ..default!()
+                },
+                None => DeviceDescriptor {
+                    required_features,
+                    required_limits,
+                    ..default!()
+                },
             })
             .await?;
 
-        // let shader_module = device.create_shader_module(ShaderModuleDescriptor {
-        //     label: None,
-        //     source: ShaderSource::Wgsl(wgsl_source(args.difficulty).into()),
-        // });
-        // let mut desc = include_spirv_raw!("../../shader.spv");
-        // desc.entry_point = "main".into();
-
-        // let desc = include_wgsl!("../../sha256-miner-d32.wgsl");
+        let algorithm = message.algorithm;
+        if layout == Layout::Fat && algorithm != Algorithm::Sha256 {
+            return Err(anyhow!(
+                "The fat layout is SHA-256 only; use `--layout packed` for `--algo {}`",
+                algorithm.name()
+            ));
+        }
+        check_kernel_params(args, &device.limits())?;
+        if args.shader_source != KernelSource::Wgsl {
+            log::warn!(
+                "Passthrough kernels have their parameters compiled in; make sure they match \
+                 --layout, --workgroup-size, --dispatch-x and --iterations"
+            );
+        }
 
-        let desc = ShaderModuleDescriptorPassthrough {
-            entry_point: "main".to_string(),
-            label: None,
-            num_workgroups: (256, 0, 0),
-            runtime_checks: Default::default(),
-            spirv: None,
-            dxil: Some(Cow::Borrowed(include_bytes!("../../shader.dxil"))),
-            msl: None,
-            hlsl: None,
-            glsl: None,
-            wgsl: None,
+        // Kernels the device can't build come back as an error rather than a panic, so `--tune`
+        // can skip them.
+        let error_scope = device.push_error_scope(ErrorFilter::Validation);
+        let shader_module = match args.shader_source {
+            KernelSource::Wgsl => device.create_shader_module(ShaderModuleDescriptor {
+                label: None,
+                source: ShaderSource::Wgsl(algorithm.wgsl(layout)),
+            }),
+            source => {
+                let path = args
+                    .shader_file
+                    .clone()
+                    .unwrap_or_else(|| source.default_file().into());
+                let bytes = std::fs::read(&path)
+                    .map_err(|e| anyhow!("Reading {} failed: {}", path.display(), e))?;
+                let mut desc = ShaderModuleDescriptorPassthrough {
+                    entry_point: "main".to_string(),
+                    num_workgroups: (args.workgroup_size, 1, 1),
+                    ..default!()
+                };
+                match source {
+                    KernelSource::Spirv => {
+                        desc.spirv =
+                            Some(Cow::Owned(wgpu::util::make_spirv_raw(&bytes).into_owned()));
+                    }
+                    _ => desc.dxil = Some(Cow::Owned(bytes)),
+                }
+                unsafe { device.create_shader_module_passthrough(desc) }
+            }
         };
-        let shader_module = unsafe { device.create_shader_module_passthrough(desc) };
-        // let shader_module = device.create_shader_module(desc);
 
         let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: None,
@@ -132,182 +1087,803 @@ impl State {
             bind_group_layouts: &[&bind_group_layout],
             immediate_size: 0,
         });
+        let mut constants = match args.shader_source {
+            KernelSource::Wgsl => vec![
+                ("WORKGROUP_SIZE", args.workgroup_size as f64),
+                ("ITERATIONS_PER_THREAD", args.iterations as f64),
+                (
+                    "RUNS_PER_DISPATCH",
+                    (args.dispatch_x * args.workgroup_size) as f64,
+                ),
+                ("HASH_LITTLE_ENDIAN", scheme.little_endian as u8 as f64),
+            ],
+            _ => vec![],
+        };
+        // Only the SHA-256 kernels declare it.
+        if args.shader_source == KernelSource::Wgsl && algorithm == Algorithm::Sha256 {
+            constants.push(("DOUBLE_SHA256", scheme.double_sha256 as u8 as f64));
+        }
         let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
             label: None,
             layout: Some(&pipeline_layout),
             module: &shader_module,
             entry_point: Some("main"),
             compilation_options: PipelineCompilationOptions {
-                constants: &[
-                    // ("WORKGROUP_SIZE", args.workgroup_size as f64),
-                    // ("ITERATIONS_PER_THREAD", args.iterations as f64),
-                    // (
-                    //     "RUNS_PER_DISPATCH",
-                    //     (args.dispatch_x * args.workgroup_size) as f64,
-                    // ),
-                    // ("DIFFICULTY_BITS", args.difficulty as f64),
-                ],
+                constants: &constants,
                 zero_initialize_workgroup_memory: false,
             },
             cache: None,
         });
+        if let Some(e) = error_scope.pop().await {
+            return Err(anyhow!("Building the kernel failed: {}", e));
+        }
 
-        let input_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: INPUT_SIZE as u64 * 4,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-        let result_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: BLOCK_BUFFER_IN_SHADER,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
-        let map_read_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: BLOCK_BUFFER_IN_SHADER,
-            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        // Messages handed out later only differ in content, not in size.
+        let work = Work {
+            index: 0,
+            message: Arc::new(message.clone()),
+            start: message.nonce().to_vec(),
+            count: 0,
+            target: Target::zero(algorithm.digest_bytes()),
+        };
+        let input_size = (layout.input_words(&work).len() * size_of::<u32>()) as u64;
+        let hit_words = layout.hit_words(algorithm);
+        let result_size =
+            ((HITS_HEADER_WORDS + hit_words * args.max_hits as usize) * size_of::<u32>()) as u64;
+        let slots = (0..args.in_flight)
+            .map(|_| {
+                let input_buffer = device.create_buffer(&BufferDescriptor {
+                    label: None,
+                    size: input_size,
+                    usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let result_buffer = device.create_buffer(&BufferDescriptor {
+                    label: None,
+                    size: result_size,
+                    usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                });
+                let map_read_buffer = device.create_buffer(&BufferDescriptor {
+                    label: None,
+                    size: result_size + if timestamps { 2 * QUERY_SIZE as u64 } else { 0 },
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let timestamps = timestamps.then(|| {
+                    let query_set = device.create_query_set(&QuerySetDescriptor {
+                        label: None,
+                        ty: QueryType::Timestamp,
+                        count: 2,
+                    });
+                    let resolve_buffer = device.create_buffer(&BufferDescriptor {
+                        label: None,
+                        size: 2 * QUERY_SIZE as u64,
+                        usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+                        mapped_at_creation: false,
+                    });
+                    (query_set, resolve_buffer)
+                });
 
-        let bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: None,
-            layout: &bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &input_buffer,
-                        offset: 0,
-                        size: None,
-                    }),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &result_buffer,
-                        offset: 0,
-                        size: None,
-                    }),
-                },
-            ],
-        });
+                let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                    label: None,
+                    layout: &bind_group_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: &input_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: &result_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                    ],
+                });
+
+                Slot {
+                    input_buffer,
+                    result_buffer,
+                    map_read_buffer,
+                    bind_group,
+                    timestamps,
+                }
+            })
+            .collect();
 
         Ok(Self {
+            timestamp_period: timestamps.then(|| queue.get_timestamp_period()),
             queue,
             device,
             pipeline,
-            input_buffer,
-            bind_group,
-            result_buffer,
-            map_read_buffer,
+            slots,
+            layout,
+            hit_words,
+            dispatch_x: args.dispatch_x,
+            max_hits: args.max_hits as usize,
+            result_size,
         })
     }
 
-    fn write_input_data(&self, buf: &[u8]) {
-        let mut input_data = [0_u32; INPUT_SIZE];
-        for (i, &b) in buf.iter().enumerate() {
-            input_data[i] = b as _;
-        }
+    fn write_input_data(&self, slot: &Slot, work: &Work) {
+        let input_data = self.layout.input_words(work);
         self.queue
-            .write_buffer(&self.input_buffer, 0, cast_slice(&input_data));
+            .write_buffer(&slot.input_buffer, 0, cast_slice(&input_data));
     }
 
-    fn compute_dispatch(&self, workgroups_x: u32) {
+    fn compute_dispatch(&self, slot: &Slot, workgroups_x: u32) -> SubmissionIndex {
         let mut encoder = self.device.create_command_encoder(&default!());
+        encoder.clear_buffer(
+            &slot.result_buffer,
+            0,
+            Some((HITS_HEADER_WORDS * size_of::<u32>()) as u64),
+        );
 
-        let mut pass = encoder.begin_compute_pass(&default!());
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: None,
+            timestamp_writes: slot.timestamps.as_ref().map(|(query_set, _)| {
+                ComputePassTimestampWrites {
+                    query_set,
+                    beginning_of_pass_write_index: Some(0),
+                    end_of_pass_write_index: Some(1),
+                }
+            }),
+        });
         pass.set_pipeline(&self.pipeline);
-        pass.set_bind_group(0, &self.bind_group, default!());
+        pass.set_bind_group(0, &slot.bind_group, default!());
         pass.dispatch_workgroups(workgroups_x, 1, 1);
         drop(pass);
 
-        encoder.copy_buffer_to_buffer(&self.result_buffer, 0, &self.map_read_buffer, 0, None);
+        encoder.copy_buffer_to_buffer(&slot.result_buffer, 0, &slot.map_read_buffer, 0, None);
+        if let Some((query_set, resolve_buffer)) = &slot.timestamps {
+            encoder.resolve_query_set(query_set, 0..2, resolve_buffer, 0);
+            encoder.copy_buffer_to_buffer(
+                resolve_buffer,
+                0,
+                &slot.map_read_buffer,
+                self.result_size,
+                None,
+            );
+        }
 
         let command_buffer = encoder.finish();
-        self.queue.submit([command_buffer]);
+        self.queue.submit([command_buffer])
     }
 
-    async fn read_result(&self, to: &mut [u8]) -> anyhow::Result<()> {
+    /// Queues `work` on `slot`, and the mapping of its result. The slot must not be in flight.
+    fn submit(&self, slot: usize, work: Work) -> InFlight {
+        let start = Instant::now();
+        let buffers = &self.slots[slot];
+        self.write_input_data(buffers, &work);
+        let submission = self.compute_dispatch(buffers, self.dispatch_x);
         let (tx, rx) = oneshot::channel();
-        self.map_read_buffer.map_async(MapMode::Read, .., |e| {
-            tx.send(e).unwrap();
+        buffers.map_read_buffer.map_async(MapMode::Read, .., |e| {
+            let _ = tx.send(e);
         });
+        InFlight {
+            slot,
+            work,
+            submission,
+            mapped: rx,
+            host_time: start.elapsed(),
+        }
+    }
+
+    /// Waits for a submitted search and returns the hits the kernel reported.
+    async fn collect(&self, in_flight: InFlight) -> anyhow::Result<SearchResult> {
         self.device.poll(PollType::Wait {
-            submission_index: None,
+            submission_index: Some(in_flight.submission),
             timeout: None,
         })?;
-        rx.await??;
+        in_flight.mapped.await??;
+        let start = Instant::now();
 
-        to[..(self.map_read_buffer.size() as usize)]
-            .copy_from_slice(cast_slice(&*self.map_read_buffer.get_mapped_range(..)));
-        self.map_read_buffer.unmap();
-        Ok(())
+        let map_read_buffer = &self.slots[in_flight.slot].map_read_buffer;
+        let mapped = map_read_buffer.get_mapped_range(..);
+        let (words, timestamps) = mapped.split_at(self.result_size as usize);
+        let words: Vec<u32> = cast_slice(words).to_vec();
+        let gpu_time = self.timestamp_period.map(|period| {
+            // Only 4-byte aligned behind `Hits`.
+            let [start, end]: [u64; 2] = pod_read_unaligned(timestamps);
+            Duration::from_nanos((end.saturating_sub(start) as f64 * period as f64) as u64)
+        });
+        drop(mapped);
+        map_read_buffer.unmap();
+
+        let found = words[0];
+        let hits = words[HITS_HEADER_WORDS..]
+            .chunks_exact(self.hit_words)
+            .take((found as usize).min(self.max_hits))
+            .map(|x| {
+                let mut nonce = in_flight.work.start.clone();
+                add_big_int(&mut nonce, x[0] as u64);
+                Hit {
+                    offset: x[0],
+                    nonce,
+                    hash: self.layout.decode_hash(&x[1..]),
+                }
+            })
+            .collect();
+        let mut result = SearchResult::new(hits, found, words[1] != 0);
+        result.gpu_time = gpu_time;
+        result.host_time = in_flight.host_time + start.elapsed();
+        Ok(result)
     }
 }
 
-fn add_big_int(data: &mut [u8; 32], n: u32) {
-    let mut carry = n;
+/// Keeps up to `depth` dispatches queued so the GPU doesn't idle while results are read back.
+/// Results come back in submission order.
+struct Pipeline<'a> {
+    state: &'a State,
+    depth: usize,
+    in_flight: VecDeque<InFlight>,
+    source: Arc<Mutex<WorkSource>>,
+    submitted: usize,
+}
 
-    for byte in data.iter_mut() {
-        if carry == 0 {
+impl<'a> Pipeline<'a> {
+    fn new(state: &'a State, depth: usize, source: Arc<Mutex<WorkSource>>) -> Self {
+        Self {
+            state,
+            depth: depth.clamp(1, state.slots.len()),
+            in_flight: VecDeque::new(),
+            source,
+            submitted: 0,
+        }
+    }
+
+    /// Tops up the queue and waits for the oldest dispatch. Returns `None` once the work source
+    /// is exhausted and everything submitted has been collected.
+    async fn next(&mut self) -> anyhow::Result<Option<(Work, SearchResult)>> {
+        while self.in_flight.len() < self.depth {
+            let Some(work) = self.source.lock().unwrap().next() else {
+                break;
+            };
+            // Slots are reused round-robin; the oldest dispatch always retires first, so the
+            // slot picked here is free.
+            let slot = self.submitted % self.depth;
+            self.in_flight.push_back(self.state.submit(slot, work));
+            self.submitted += 1;
+        }
+        let Some(oldest) = self.in_flight.pop_front() else {
+            return Ok(None);
+        };
+        let work = oldest.work.clone();
+        Ok(Some((work, self.state.collect(oldest).await?)))
+    }
+}
+
+/// Runs dispatches of `message` `depth` deep until `stop(<dispatches done>, <time taken>)` and
+/// returns the hashrate.
+async fn measure_hashrate(
+    state: &State,
+    message: &Message,
+    target: &Target,
+    depth: usize,
+    hashes_per_dispatch: u32,
+    stop: impl Fn(usize, Duration) -> bool,
+) -> anyhow::Result<f64> {
+    let source = WorkSource::new(
+        message.clone(),
+        target.clone(),
+        hashes_per_dispatch,
+        None,
+        None,
+    );
+    let mut pipeline = Pipeline::new(state, depth, Arc::new(Mutex::new(source)));
+    let start = Instant::now();
+    let mut hashes = 0_u64;
+    let mut dispatches = 0;
+    while !stop(dispatches, start.elapsed()) {
+        let Some((work, _)) = pipeline.next().await? else {
+            break;
+        };
+        hashes += work.count as u64;
+        dispatches += 1;
+    }
+    let elapsed = start.elapsed();
+    // Drain so the next measurement starts with an idle GPU.
+    while let Some(x) = pipeline.in_flight.pop_front() {
+        state.collect(x).await?;
+    }
+    Ok(hashes as f64 / elapsed.as_secs_f64())
+}
+
+/// The parameters the `--benchmark` results depend on.
+#[derive(Serialize)]
+struct BenchmarkSetup {
+    workgroup_size: u32,
+    dispatch_x: u32,
+    iterations: u32,
+    hashes_per_dispatch: u32,
+    in_flight: u32,
+    layout: Layout,
+    algorithm: Algorithm,
+    message_bytes: usize,
+    double_sha256: bool,
+    warmup_secs: f64,
+    duration_secs: f64,
+}
+
+#[derive(Serialize)]
+struct DeviceBenchmark {
+    adapter: String,
+    backend: String,
+    driver: String,
+    dispatches: usize,
+    hashes: u64,
+    /// Wall-clock time of the measured dispatches.
+    measured_secs: f64,
+    hashrate: f64,
+    /// Duration of the compute pass; `None` without `TIMESTAMP_QUERY`.
+    gpu_ms_per_dispatch: Option<f64>,
+    /// Host time submitting and reading back, waiting excluded.
+    host_ms_per_dispatch: f64,
+}
+
+#[derive(Serialize)]
+struct BenchmarkReport {
+    setup: BenchmarkSetup,
+    devices: Vec<DeviceBenchmark>,
+}
+
+/// Mines `message` on `state` at a target no hash meets, for `--warmup-secs` and then measured
+/// for `--duration`.
+async fn benchmark(
+    args: &Args,
+    state: &State,
+    adapter: &Adapter,
+    message: &Message,
+    hashes_per_dispatch: u32,
+) -> anyhow::Result<DeviceBenchmark> {
+    let target = Target::zero(message.algorithm.digest_bytes());
+    let mut source = WorkSource::new(message.clone(), target, hashes_per_dispatch, None, None);
+    // Nothing can be found, so wrapping around the nonce field and repeating nonces is fine.
+    source.space = None;
+    let mut pipeline = Pipeline::new(state, args.in_flight as usize, Arc::new(Mutex::new(source)));
+
+    let start = Instant::now();
+    while start.elapsed().as_secs_f64() < args.warmup_secs {
+        pipeline.next().await?;
+    }
+    let measure_start = Instant::now();
+    let mut dispatches = 0;
+    let mut hashes = 0_u64;
+    let mut gpu_time = Some(Duration::ZERO);
+    let mut host_time = Duration::ZERO;
+    while measure_start.elapsed().as_secs_f64() < args.duration {
+        let Some((work, result)) = pipeline.next().await? else {
             break;
+        };
+        dispatches += 1;
+        hashes += work.count as u64;
+        gpu_time = gpu_time.zip(result.gpu_time).map(|(a, b)| a + b);
+        host_time += result.host_time;
+    }
+    let measured = measure_start.elapsed();
+    while let Some(x) = pipeline.in_flight.pop_front() {
+        state.collect(x).await?;
+    }
+
+    let per_dispatch = |x: Duration| x.as_secs_f64() * 1000.0 / dispatches.max(1) as f64;
+    let info = adapter.get_info();
+    Ok(DeviceBenchmark {
+        adapter: info.name,
+        backend: info.backend.to_string(),
+        driver: format!("{} {}", info.driver, info.driver_info),
+        dispatches,
+        hashes,
+        measured_secs: measured.as_secs_f64(),
+        hashrate: hashes as f64 / measured.as_secs_f64(),
+        gpu_ms_per_dispatch: gpu_time.map(per_dispatch),
+        host_ms_per_dispatch: per_dispatch(host_time),
+    })
+}
+
+/// Benchmarks a grid of kernel parameters within the adapter's limits for `--tune-duration`
+/// each, prints them ranked and returns the fastest. Configurations that fail are skipped, and
+/// so are dispatches as wide as one that took longer than `--tune-duration` by itself.
+async fn tune(
+    args: &Args,
+    adapter: &Adapter,
+    message: &Message,
+    scheme: &Scheme,
+) -> anyhow::Result<Profile> {
+    let limits = adapter.limits();
+    let max_workgroup_size = limits
+        .max_compute_workgroup_size_x
+        .min(limits.max_compute_invocations_per_workgroup);
+    let duration = Duration::from_secs_f64(args.tune_duration);
+    // Never met, so every configuration does the same work.
+    let target = Target::zero(message.algorithm.digest_bytes());
+    let mut results = Vec::new();
+    // Nonces per dispatch of the narrowest dispatch that took longer than `duration`.
+    let mut too_wide: Option<u32> = None;
+    let workgroup_sizes = [32, 64, 128, 256, 512, 1024]
+        .into_iter()
+        .filter(|&x| x <= max_workgroup_size);
+    for workgroup_size in workgroup_sizes {
+        let dispatch_xs = [256, 1024, 4096, 16384]
+            .into_iter()
+            .filter(|&x| x <= limits.max_compute_workgroups_per_dimension);
+        for dispatch_x in dispatch_xs {
+            for iterations in [1, 4, 16, 64] {
+                let mut args = args.clone();
+                args.workgroup_size = workgroup_size;
+                args.dispatch_x = dispatch_x;
+                args.iterations = iterations;
+                let Ok(hashes_per_dispatch) = hashes_per_dispatch(&args) else {
+                    continue;
+                };
+                let config = format!(
+                    "  --workgroup-size {} --dispatch-x {} --iterations {}",
+                    workgroup_size, dispatch_x, iterations
+                );
+                if too_wide.is_some_and(|x| hashes_per_dispatch >= x) {
+                    eprintln!(
+                        "{}: skipped: a dispatch this wide takes longer than `--tune-duration`",
+                        config
+                    );
+                    continue;
+                }
+                let state = match State::new(&args, adapter, message, scheme, args.layout).await {
+                    Ok(x) => x,
+                    Err(e) => {
+                        eprintln!("{}: skipped: {}", config, e);
+                        continue;
+                    }
+                };
+                let depth = args.in_flight as usize;
+                let hashrate = async {
+                    let single = || {
+                        measure_hashrate(
+                            &state,
+                            message,
+                            &target,
+                            1,
+                            hashes_per_dispatch,
+                            |n, _| n >= 1,
+                        )
+                    };
+                    // The first dispatch pays for setting up the pipeline.
+                    single().await?;
+                    let start = Instant::now();
+                    let hashrate = single().await?;
+                    if start.elapsed() > duration {
+                        // Only dispatches narrower than `too_wide` get here.
+                        too_wide = Some(hashes_per_dispatch);
+                        eprintln!(
+                            "{}: one dispatch took {:?}, longer than `--tune-duration`; wider \
+                             ones are skipped",
+                            config,
+                            start.elapsed()
+                        );
+                        return Ok(hashrate);
+                    }
+                    measure_hashrate(
+                        &state,
+                        message,
+                        &target,
+                        depth,
+                        hashes_per_dispatch,
+                        |_, elapsed| elapsed >= duration,
+                    )
+                    .await
+                }
+                .await;
+                let hashrate = match hashrate {
+                    Ok(x) => x,
+                    Err(e) => {
+                        eprintln!("{}: skipped: {}", config, e);
+                        continue;
+                    }
+                };
+                eprintln!(
+                    "{}: {} H/s",
+                    config,
+                    (hashrate.round() as u64).to_formatted_string(&Locale::en)
+                );
+                results.push(Profile {
+                    workgroup_size,
+                    dispatch_x,
+                    iterations,
+                    layout: args.layout,
+                    hashrate,
+                });
+            }
         }
+    }
+    results.sort_by(|a, b| b.hashrate.total_cmp(&a.hashrate));
 
-        let sum = *byte as u32 + carry;
+    println!(
+        "  {:>4}  {:>14}  {:>10}  {:>10}  {:>16}",
+        "rank", "workgroup-size", "dispatch-x", "iterations", "H/s"
+    );
+    for (rank, x) in results.iter().enumerate() {
+        println!(
+            "  {:>4}  {:>14}  {:>10}  {:>10}  {:>16}",
+            rank + 1,
+            x.workgroup_size,
+            x.dispatch_x,
+            x.iterations,
+            (x.hashrate.round() as u64).to_formatted_string(&Locale::en)
+        );
+    }
+    results
+        .first()
+        .copied()
+        .ok_or_else(|| anyhow!("No configuration fits the adapter's limits"))
+}
 
-        *byte = (sum & 0xFF) as u8;
+/// Checks the kernel parameters against the device limits.
+fn check_kernel_params(args: &Args, limits: &Limits) -> anyhow::Result<()> {
+    let max_workgroup_size = limits
+        .max_compute_workgroup_size_x
+        .min(limits.max_compute_invocations_per_workgroup);
+    if !(1..=max_workgroup_size).contains(&args.workgroup_size) {
+        return Err(anyhow!(
+            "`--workgroup-size` must be in 1..={}",
+            max_workgroup_size
+        ));
+    }
+    if !(1..=limits.max_compute_workgroups_per_dimension).contains(&args.dispatch_x) {
+        return Err(anyhow!(
+            "`--dispatch-x` must be in 1..={}",
+            limits.max_compute_workgroups_per_dimension
+        ));
+    }
+    if args.iterations == 0 {
+        return Err(anyhow!("`--iterations` must be at least 1"));
+    }
+    hashes_per_dispatch(args).map(|_| ())
+}
 
-        carry = sum >> 8;
+/// Nonces covered by one dispatch. This must fit in the `u32` offset the kernel adds to `start`.
+fn hashes_per_dispatch(args: &Args) -> anyhow::Result<u32> {
+    args.dispatch_x
+        .checked_mul(args.workgroup_size)
+        .and_then(|x| x.checked_mul(args.iterations))
+        .ok_or_else(|| {
+            anyhow!("`--workgroup-size * --dispatch-x * --iterations` must fit in a u32")
+        })
+}
+
+/// Checks a GPU hit against the CPU reference.
+fn verify_hit(hit: &Hit, work: &Work, scheme: &Scheme) -> anyhow::Result<()> {
+    if hit.offset >= work.count {
+        return Err(anyhow!(
+            "GPU hit at offset {} is outside the dispatched range {} + [0, {})",
+            hit.offset,
+            hex::encode(&work.start),
+            work.count
+        ));
+    }
+    // Hash the whole message, independent of the midstate.
+    let hash = scheme.hash(&work.message.with_nonce(&hit.nonce));
+    if hash != hit.hash {
+        return Err(anyhow!(
+            "GPU hit with nonce {} reports {} {}, but it is {}",
+            hex::encode(&hit.nonce),
+            scheme.name(),
+            hex::encode(&hit.hash),
+            hex::encode(hash)
+        ));
     }
+    if !scheme.is_hit(&hash, &work.target) {
+        return Err(anyhow!(
+            "GPU hit with nonce {} doesn't meet the target: {} is {}",
+            hex::encode(&hit.nonce),
+            scheme.name(),
+            hex::encode(hash)
+        ));
+    }
+    Ok(())
 }
 
-#[inline(always)]
-fn convert_fat_buf(buf: &FatSha256Buf) -> [u8; SHA256_BYTES] {
-    buf.map(|x| x as u8)
+/// The message being mined, and where its nonce field is.
+#[derive(Debug, Clone)]
+struct Message {
+    /// With the nonce field at its start value.
+    bytes: Vec<u8>,
+    nonce_offset: usize,
+    nonce_width: usize,
+    algorithm: Algorithm,
+    /// Hash state after the constant blocks in front of the one holding the nonce field.
+    midstate: Vec<u32>,
 }
 
-fn generate_check_difficulty_wgsl(difficulty_bits: u32) -> String {
-    let mut conditions = Vec::new();
+impl Message {
+    fn new(bytes: Vec<u8>, nonce_offset: usize, nonce_width: usize, algorithm: Algorithm) -> Self {
+        let mut message = Self {
+            bytes,
+            nonce_offset,
+            nonce_width,
+            algorithm,
+            midstate: Vec::new(),
+        };
+        message.midstate = algorithm.midstate(&message.bytes[..message.prefix_len()]);
+        message
+    }
 
-    let full_bytes = difficulty_bits / 8;
-    for i in 0..full_bytes {
-        conditions.push(format!("buf[{}] == 0u", i));
+    fn from_args(args: &Args) -> anyhow::Result<Self> {
+        let (mut bytes, nonce_offset, nonce_width) = if args.bitcoin.enabled() {
+            (
+                bitcoin::header_from_args(&args.bitcoin)?,
+                bitcoin::NONCE_OFFSET,
+                bitcoin::NONCE_WIDTH,
+            )
+        } else {
+            let bytes = match &args.message {
+                Some(x) => hex::decode(x)?,
+                None => vec![0; 32],
+            };
+            let width = args
+                .nonce_width
+                .unwrap_or(bytes.len().saturating_sub(args.nonce_offset));
+            (bytes, args.nonce_offset, width)
+        };
+        if nonce_width == 0 || nonce_offset + nonce_width > bytes.len() {
+            return Err(anyhow!(
+                "The nonce field ({} bytes at {}) must be non-empty and lie in the {}-byte message",
+                nonce_width,
+                nonce_offset,
+                bytes.len()
+            ));
+        }
+        if let Some(start) = &args.start {
+            let start = hex::decode(start)?;
+            if start.len() > nonce_width {
+                return Err(anyhow!("Length of `start` must be <= {}", nonce_width));
+            }
+            let field = &mut bytes[nonce_offset..][..nonce_width];
+            field.fill(0);
+            field[..start.len()].copy_from_slice(&start);
+        }
+        Ok(Self::new(bytes, nonce_offset, nonce_width, args.algo))
     }
 
-    let remaining_bits = difficulty_bits % 8;
-    if remaining_bits > 0 {
-        let shift = 8 - remaining_bits;
-        conditions.push(format!("(buf[{}] >> {}u) == 0u", full_bytes, shift));
+    /// Nonces in the `--end` or `--count` range from the nonce field's start value.
+    fn range_from_args(&self, args: &Args) -> anyhow::Result<Option<u64>> {
+        let range = if let Some(end) = &args.end {
+            let mut end = hex::decode(end)?;
+            if end.len() > self.nonce_width {
+                return Err(anyhow!("Length of `end` must be <= {}", self.nonce_width));
+            }
+            end.resize(self.nonce_width, 0);
+            // `end - start`, little-endian.
+            let mut borrow = false;
+            for (x, &y) in end.iter_mut().zip(self.nonce()) {
+                let (difference, overflow) = x.overflowing_sub(y);
+                let (difference, overflow_borrow) = difference.overflowing_sub(borrow as u8);
+                *x = difference;
+                borrow = overflow || overflow_borrow;
+            }
+            if borrow || end.iter().all(|&x| x == 0) {
+                return Err(anyhow!("`--end` must be greater than the start"));
+            }
+            if end.iter().skip(size_of::<u64>()).any(|&x| x != 0) {
+                return Err(anyhow!("The range can't be more than 2^64 nonces"));
+            }
+            end.resize(size_of::<u64>(), 0);
+            u64::from_le_bytes(end.try_into().unwrap())
+        } else if let Some(count) = args.count {
+            if count == 0 {
+                return Err(anyhow!("`--count` must be at least 1"));
+            }
+            // The field would wrap around to the start.
+            if self.nonce_width < size_of::<u64>() && count > 1 << (self.nonce_width * 8) {
+                return Err(anyhow!(
+                    "`--count` is larger than the {}-byte nonce field",
+                    self.nonce_width
+                ));
+            }
+            count
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(range))
     }
 
-    let final_condition = if conditions.is_empty() {
-        "true".to_string()
-    } else {
-        conditions.join(" && ")
-    };
+    fn nonce(&self) -> &[u8] {
+        &self.bytes[self.nonce_offset..][..self.nonce_width]
+    }
 
-    format!(
-        r#"
-fn check_difficulty(buf: ptr<function, array<u32, SHA256_BLOCK_SIZE>>) -> bool {{
-    return {};
-}}
-"#,
-        final_condition
-    )
+    fn with_nonce(&self, nonce: &[u8]) -> Vec<u8> {
+        let mut bytes = self.bytes.clone();
+        bytes[self.nonce_offset..][..self.nonce_width].copy_from_slice(nonce);
+        bytes
+    }
+
+    /// Length of the constant blocks in front of the one holding the nonce field.
+    fn prefix_len(&self) -> usize {
+        let block_bytes = self.algorithm.block_bytes();
+        self.nonce_offset / block_bytes * block_bytes
+    }
+
+    /// The fat kernel's `Input` for `work` of this message.
+    fn fat_input_words(&self, work: &Work) -> Vec<u32> {
+        let prefix_len = self.prefix_len();
+        let prefix_bitlen = prefix_len as u64 * 8;
+        let mut words = self.midstate.clone();
+        words.extend([
+            prefix_bitlen as u32,
+            (prefix_bitlen >> 32) as u32,
+            (self.nonce_offset - prefix_len) as u32,
+            self.nonce_width as u32,
+            work.count,
+        ]);
+        words.extend(work.target.0.iter().map(|&x| x as u32));
+        debug_assert_eq!(words.len(), FAT_INPUT_HEADER_WORDS);
+        words.extend(
+            self.with_nonce(&work.start)[prefix_len..]
+                .iter()
+                .map(|&x| x as u32),
+        );
+        words
+    }
+
+    /// The packed kernel's `Input` for `work` of this message. The tail is padded here, so the
+    /// kernel only runs whole blocks.
+    fn packed_input_words(&self, work: &Work) -> Vec<u32> {
+        let prefix_len = self.prefix_len();
+        let mut words = self.midstate.clone();
+        words.extend([
+            (self.nonce_offset - prefix_len) as u32,
+            self.nonce_width as u32,
+            work.count,
+        ]);
+        words.extend(
+            work.target
+                .0
+                .chunks_exact(size_of::<u32>())
+                .map(|x| u32::from_be_bytes(x.try_into().unwrap())),
+        );
+        // Midstate, nonce offset and width, nonce count, and the target.
+        debug_assert_eq!(
+            words.len(),
+            self.midstate.len() + 3 + self.algorithm.digest_bytes() / size_of::<u32>()
+        );
+
+        let mut tail = self.with_nonce(&work.start).split_off(prefix_len);
+        self.algorithm.pad(&mut tail, self.bytes.len());
+        words.extend(tail.chunks_exact(size_of::<u32>()).map(|x| {
+            let x = x.try_into().unwrap();
+            if self.algorithm.big_endian() {
+                u32::from_be_bytes(x)
+            } else {
+                u32::from_le_bytes(x)
+            }
+        }));
+        words
+    }
 }
 
-fn wgsl_source(difficulty_bits: u32) -> String {
-    let mut source = include_str!("../sha256-miner.wgsl")
-        .lines()
-        .collect::<Vec<_>>();
-    source.remove(0);
-    let generated = generate_check_difficulty_wgsl(difficulty_bits);
-    for x in generated.lines().into_iter().rev() {
-        source.insert(0, x);
+fn add_big_int(data: &mut [u8], n: u64) {
+    let mut carry = n;
+
+    for byte in data.iter_mut() {
+        if carry == 0 {
+            break;
+        }
+
+        let sum = *byte as u64 + carry;
+
+        *byte = (sum & 0xFF) as u8;
+
+        carry = sum >> 8;
     }
-    source.join("\n")
+}
+
+#[inline(always)]
+fn convert_fat_buf(buf: &FatSha256Buf) -> [u8; SHA256_BYTES] {
+    buf.map(|x| x as u8)
 }
 
 #[tokio::main]
@@ -315,49 +1891,423 @@ async fn main() -> anyhow::Result<()> {
     let program_start = Instant::now();
     set_up_logger();
 
-    let args = Args::parse();
-    let runs_per_dispatch = args.dispatch_x * args.workgroup_size;
-
+    let mut args = Args::parse();
+    if args.adapter.list_adapters {
+        print_adapters(&args.adapter.create_instance()?).await;
+        return Ok(());
+    }
     eprintln!("Args: {:?}", args);
+    if args.algo != Algorithm::Sha256 {
+        if args.bitcoin.enabled() {
+            return Err(anyhow!("Bitcoin header mining needs `--algo sha256`"));
+        }
+        if args.compare_layouts.is_some() {
+            return Err(anyhow!("`--compare-layouts` needs `--algo sha256`"));
+        }
+    }
 
-    let arg_start = hex::decode(args.start.as_ref().map(|x| x.as_str()).unwrap_or_default())?;
-    if arg_start.len() > 32 {
-        return Err(anyhow!("Length of `start` must be <= 32"));
+    let message = Message::from_args(&args)?;
+    let coinbase = bitcoin::Coinbase::from_args(&args.bitcoin)?;
+    let message = match &coinbase {
+        Some(coinbase) => coinbase.apply(&message),
+        None => message,
+    };
+    let scheme = Scheme {
+        algorithm: args.algo,
+        double_sha256: args.bitcoin.enabled(),
+        little_endian: args.bitcoin.enabled(),
+    };
+    let digest_bytes = args.algo.digest_bytes();
+    let target = if let Some(hex) = &args.target {
+        Target::from_hex(hex, digest_bytes)?
+    } else if let Some(difficulty) = args.target_difficulty {
+        Target::from_difficulty(difficulty, digest_bytes)?
+    } else if args.bitcoin.enabled() {
+        Target(bitcoin::decode_bits(bitcoin::bits(&message.bytes))?.to_vec())
+    } else {
+        Target::leading_zeros(args.difficulty, digest_bytes)?
+    };
+    eprintln!("Target: {}", hex::encode(&target.0));
+    let range = message.range_from_args(&args)?;
+    let threads = args
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |x| x.get()));
+    let adapters = match args.device {
+        MinerDevice::Gpu => {
+            let instance = args.adapter.create_instance()?;
+            let adapters = match &args.adapters {
+                Some(list) => args.adapter.select_adapters(&instance, list).await?,
+                None => vec![args.adapter.select_adapter(&instance, None).await?],
+            };
+            for (i, adapter) in adapters.iter().enumerate() {
+                let info = adapter.get_info();
+                eprintln!("Device {}: {} ({})", i, info.name, info.backend);
+            }
+            adapters
+        }
+        MinerDevice::Cpu => {
+            eprintln!("Mining on the CPU with {} threads", threads);
+            Vec::new()
+        }
+    };
+    let depth = args.in_flight as usize;
+    let format = |x: f64| (x.round() as u64).to_formatted_string(&Locale::en);
+    if args.tune {
+        if adapters.is_empty() {
+            return Err(anyhow!("`--tune` needs `--device gpu`"));
+        }
+        if args.shader_source != KernelSource::Wgsl {
+            return Err(anyhow!("`--tune` needs `--shader-source wgsl`"));
+        }
+        let path = args
+            .profile
+            .clone()
+            .unwrap_or_else(|| "sha256-miner-profiles.json".into());
+        // Other adapters' profiles are kept.
+        let mut profiles = if path.exists() {
+            Profile::load_all(&path)?
+        } else {
+            BTreeMap::new()
+        };
+        let mut failed = 0;
+        for (i, adapter) in adapters.iter().enumerate() {
+            println!("Device {}:", i);
+            match tune(&args, adapter, &message, &scheme).await {
+                Ok(profile) => {
+                    profiles.insert(Profile::key(adapter), profile);
+                }
+                Err(e) => {
+                    eprintln!("Device {}: {}", i, e);
+                    failed += 1;
+                }
+            }
+        }
+        write_report(&path, OutputFormat::Json, &profiles)?;
+        eprintln!("Saved the fastest parameters to {}", path.display());
+        if failed > 0 {
+            return Err(anyhow!("Tuning failed on {} devices", failed));
+        }
+        return Ok(());
+    }
+    if let Some(path) = &args.profile {
+        let [adapter] = adapters.as_slice() else {
+            return Err(anyhow!("`--profile` needs a single GPU adapter"));
+        };
+        let key = Profile::key(adapter);
+        let profile = *Profile::load_all(path)?
+            .get(&key)
+            .ok_or_else(|| anyhow!("{} has no profile for {}", path.display(), key))?;
+        eprintln!("Using the profile for {}: {:?}", key, profile);
+        profile.apply(&mut args);
+    }
+    let hashes_per_dispatch = hashes_per_dispatch(&args)?;
+    if let Some(dispatches) = args.compare_layouts {
+        if adapters.is_empty() {
+            return Err(anyhow!("`--compare-layouts` needs `--device gpu`"));
+        }
+        let mut source = WorkSource::new(
+            message.clone(),
+            target.clone(),
+            hashes_per_dispatch,
+            None,
+            range,
+        );
+        let work = source.next().unwrap();
+        eprintln!("Searching the first dispatch on the CPU");
+        let expected = cpu::search(
+            &work.message,
+            &work.start,
+            work.count,
+            &scheme,
+            &work.target,
+            threads,
+        );
+        let mut mismatches = 0;
+        for (i, adapter) in adapters.iter().enumerate() {
+            println!("Device {}:", i);
+            for layout in [Layout::Fat, Layout::Packed] {
+                let state = State::new(&args, adapter, &message, &scheme, layout).await?;
+                let hashrate = measure_hashrate(
+                    &state,
+                    &message,
+                    &target,
+                    depth,
+                    hashes_per_dispatch,
+                    |n, _| n >= dispatches,
+                )
+                .await?;
+                let result = state.collect(state.submit(0, work.clone())).await?;
+                // On overflow the kernel keeps an arbitrary subset of the hits.
+                let correct = result.found == expected.found
+                    && result.hits.iter().all(|x| expected.hits.contains(x));
+                if !correct {
+                    mismatches += 1;
+                }
+                println!(
+                    "  {:?}: {} H/s, {} of {} hits, {}",
+                    layout,
+                    format(hashrate),
+                    result.found,
+                    expected.found,
+                    if correct {
+                        "matching the CPU"
+                    } else {
+                        "NOT matching the CPU"
+                    }
+                );
+            }
+        }
+        if mismatches > 0 {
+            return Err(anyhow!("{} layout runs disagree with the CPU", mismatches));
+        }
+        return Ok(());
     }
 
-    let state = State::new(&args).await?;
-    let mut input_data = [0_u8; INPUT_SIZE];
-    input_data[..arg_start.len()].copy_from_slice(&arg_start);
-    let mut result = [0_u32; SHA256_BYTES];
-    let mut counter = 0_usize;
+    // Checked before any pipeline is built, so a wrong `--resume` fails right away.
+    let params = SearchParams::new(&args, &message, coinbase.as_ref(), range, &target, &scheme);
+    let resumed = match &args.resume {
+        Some(path) => {
+            let checkpoint = Checkpoint::load(path)?;
+            checkpoint.check_params(&params)?;
+            eprintln!(
+                "Resuming after {} dispatches ({} hashes, {:?})",
+                checkpoint.dispatches,
+                checkpoint.hashes.to_formatted_string(&Locale::en),
+                Duration::from_secs_f64(checkpoint.elapsed_secs)
+            );
+            Some(checkpoint)
+        }
+        None => None,
+    };
+    let checkpoint_path = args.checkpoint.clone().or_else(|| args.resume.clone());
+    let mut searched = resumed.as_ref().map_or(0, |x| x.dispatches);
+    let prior_hashes = resumed.as_ref().map_or(0, |x| x.hashes);
+    let mut searched_hashes = prior_hashes;
+    let prior_elapsed = resumed
+        .as_ref()
+        .map_or(Duration::ZERO, |x| Duration::from_secs_f64(x.elapsed_secs));
+
+    let mut states = Vec::new();
+    for adapter in &adapters {
+        states.push(State::new(&args, adapter, &message, &scheme, args.layout).await?);
+    }
+    if args.benchmark {
+        if states.is_empty() {
+            return Err(anyhow!("`--benchmark` needs `--device gpu`"));
+        }
+        let setup = BenchmarkSetup {
+            workgroup_size: args.workgroup_size,
+            dispatch_x: args.dispatch_x,
+            iterations: args.iterations,
+            hashes_per_dispatch,
+            in_flight: args.in_flight,
+            layout: args.layout,
+            algorithm: args.algo,
+            message_bytes: message.bytes.len(),
+            double_sha256: scheme.double_sha256,
+            warmup_secs: args.warmup_secs,
+            duration_secs: args.duration,
+        };
+        let mut devices = Vec::new();
+        for (i, (state, adapter)) in states.iter().zip(&adapters).enumerate() {
+            let result = benchmark(&args, state, adapter, &message, hashes_per_dispatch).await?;
+            eprintln!(
+                "Device {}: {} H/s over {} dispatches, GPU time {} ms, host time {:.3} ms per \
+                 dispatch",
+                i,
+                format(result.hashrate),
+                result.dispatches,
+                result
+                    .gpu_ms_per_dispatch
+                    .map_or("unknown".into(), |x| format!("{:.3}", x)),
+                result.host_ms_per_dispatch
+            );
+            devices.push(result);
+        }
+        let report = BenchmarkReport { setup, devices };
+        match &args.benchmark_output {
+            Some(path) => write_report(path, OutputFormat::Json, &report)?,
+            None => println!("{}", serde_json::to_string_pretty(&report)?),
+        }
+        return Ok(());
+    }
+    if let Some(dispatches) = args.pipeline_benchmark {
+        if states.is_empty() {
+            return Err(anyhow!("`--pipeline-benchmark` needs `--device gpu`"));
+        }
+        for (i, state) in states.iter().enumerate() {
+            let synchronous =
+                measure_hashrate(state, &message, &target, 1, hashes_per_dispatch, |n, _| {
+                    n >= dispatches
+                })
+                .await?;
+            let pipelined = measure_hashrate(
+                state,
+                &message,
+                &target,
+                depth,
+                hashes_per_dispatch,
+                |n, _| n >= dispatches,
+            )
+            .await?;
+            println!("Device {}:", i);
+            println!("  synchronous: {} H/s", format(synchronous));
+            println!(
+                "  pipelined ({} in flight): {} H/s",
+                depth,
+                format(pipelined)
+            );
+            println!(
+                "  readback overhead removed: {:.1}%",
+                (1.0 - synchronous / pipelined) * 100.0
+            );
+        }
+        return Ok(());
+    }
+
+    let devices = states.len().max(1);
+    let verify = args.verify && !states.is_empty();
+    let mut source = WorkSource::new(message, target, hashes_per_dispatch, coinbase, range);
+    source.skip(searched);
+    // Every device takes the next dispatch from here, so their nonce ranges never overlap.
+    let source = Arc::new(Mutex::new(source));
+    let (tx, rx) = mpsc::channel();
+    // Indexed by device.
+    let mut workers = Vec::new();
+    if states.is_empty() {
+        let tx = tx.clone();
+        let source = source.clone();
+        workers.push(thread::spawn(move || {
+            while let Some(work) = source.lock().unwrap().next() {
+                let result = cpu::search(
+                    &work.message,
+                    &work.start,
+                    work.count,
+                    &scheme,
+                    &work.target,
+                    threads,
+                );
+                if tx.send(Ok((0, work, result))).is_err() {
+                    break;
+                }
+            }
+        }));
+    }
+    for (i, state) in states.into_iter().enumerate() {
+        let tx = tx.clone();
+        let source = source.clone();
+        workers.push(thread::spawn(move || {
+            let mut pipeline = Pipeline::new(&state, depth, source);
+            loop {
+                let result = match pollster::block_on(pipeline.next()) {
+                    Ok(Some((work, result))) => Ok((i, work, result)),
+                    Ok(None) => break,
+                    Err(e) => Err(e),
+                };
+                let stop = result.is_err();
+                if tx.send(result).is_err() || stop {
+                    break;
+                }
+            }
+        }));
+    }
+    drop(tx);
+
     let compute_start = Instant::now();
-    let mut hashes = 0_u64;
-    loop {
+    let mut last_checkpoint = Instant::now();
+    // Nonce counts of the dispatches finished ahead of `searched`.
+    let mut finished = BTreeMap::new();
+    let mut discarded = 0;
+    let mut device_hashes = vec![0_u64; devices];
+    let hashrate = |hashes: u64| {
+        ((hashes as f64 / compute_start.elapsed().as_secs_f64()).round() as u64)
+            .to_formatted_string(&Locale::en)
+    };
+    for dispatch in rx.iter() {
+        let (device, work, result) = dispatch?;
+        device_hashes[device] += work.count as u64;
+        let hashes = device_hashes.iter().sum::<u64>();
+        finished.insert(work.index, work.count);
+        while let Some(count) = finished.remove(&searched) {
+            searched += 1;
+            searched_hashes += count as u64;
+        }
+        if let Some(path) = &checkpoint_path
+            && last_checkpoint.elapsed().as_secs() >= args.checkpoint_interval
+        {
+            let (next_nonce, extranonce) = source.lock().unwrap().position(searched);
+            let checkpoint = Checkpoint {
+                params: params.clone(),
+                dispatches: searched,
+                next_nonce: hex::encode(next_nonce),
+                extranonce: extranonce.map(hex::encode),
+                hashes: searched_hashes,
+                elapsed_secs: (prior_elapsed + compute_start.elapsed()).as_secs_f64(),
+            };
+            checkpoint.save(path)?;
+            last_checkpoint = Instant::now();
+        }
+        let per_device = if devices > 1 {
+            format!(
+                " ({})",
+                device_hashes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &x)| format!("{}: {}", i, hashrate(x)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        } else {
+            String::new()
+        };
         eprintln!(
-            "dispatch: {}, start: {}, elapsed: {:?}, hashes: {}, hashrate: {} H/s",
-            counter,
-            hex::encode(input_data),
-            compute_start.elapsed(),
-            hashes.to_formatted_string(&Locale::en),
-            ((hashes as f64 / compute_start.elapsed().as_secs_f64()).round() as u64)
-                .to_formatted_string(&Locale::en)
+            "dispatch: {}, device: {}, start: {}, elapsed: {:?}, hashes: {}, hashrate: {} H/s{}",
+            work.index,
+            device,
+            hex::encode(&work.start),
+            prior_elapsed + compute_start.elapsed(),
+            (prior_hashes + hashes).to_formatted_string(&Locale::en),
+            hashrate(hashes),
+            per_device
         );
-        state.write_input_data(&input_data);
-        state.compute_dispatch(args.dispatch_x);
-        let hashes_computed = runs_per_dispatch * args.iterations;
-        hashes += hashes_computed as u64;
-        add_big_int(&mut input_data, hashes_computed);
-        state.read_result(cast_slice_mut(&mut result)).await?;
-        if result.iter().any(|x| *x != 0) {
-            // print result and exit
-            use sha2::Digest;
-            let mut hasher = sha2::Sha256::new();
-            hasher.update(convert_fat_buf(&result));
-            let hash = hex::encode(hasher.finalize());
+        if result.found > 0 {
+            if verify {
+                let verified = result
+                    .hits
+                    .iter()
+                    .try_for_each(|hit| verify_hit(hit, &work, &scheme));
+                if let Err(e) = verified {
+                    eprintln!("Device {}: {}; discarding its hits", device, e);
+                    discarded += 1;
+                    continue;
+                }
+                eprintln!("{} GPU hits verified on the CPU", result.hits.len());
+            }
+            if result.overflow {
+                eprintln!(
+                    "Hit buffer overflowed: {} hits found, {} kept; raise `--max-hits`",
+                    result.found,
+                    result.hits.len()
+                );
+            }
 
+            // print result and exit
             println!("Result:");
-            println!("  input: {}", hex::encode(convert_fat_buf(&result)));
-            println!("  sha256: {}", hash);
+            println!("  device: {}", device);
+            for hit in &result.hits {
+                println!("  offset: {}", hit.offset);
+                println!("    nonce: {}", hex::encode(&hit.nonce));
+                println!(
+                    "    input: {}",
+                    hex::encode(work.message.with_nonce(&hit.nonce))
+                );
+                println!("    {}: {}", scheme.name(), hex::encode(&hit.hash));
+                if args.bitcoin.enabled() {
+                    let mut block_hash = hit.hash.clone();
+                    block_hash.reverse();
+                    println!("    block hash: {}", hex::encode(block_hash));
+                }
+            }
             println!(
                 "  preparation time: {:?}",
                 compute_start.duration_since(program_start)
@@ -365,6 +2315,493 @@ async fn main() -> anyhow::Result<()> {
             println!("  computation elapsed: {:?}", compute_start.elapsed());
             exit(0);
         }
-        counter += 1;
+    }
+    // The channel also closes when a device's thread dies, with its dispatches unfinished.
+    for (device, worker) in workers.into_iter().enumerate() {
+        if worker.join().is_err() {
+            return Err(anyhow!(
+                "Device {} panicked; {} nonces searched, the search is incomplete",
+                device,
+                searched_hashes.to_formatted_string(&Locale::en)
+            ));
+        }
+    }
+    let source = source.lock().unwrap();
+    if !source.exhausted() || searched < source.dispatches {
+        return Err(anyhow!(
+            "The devices stopped after {} of {} dispatches without exhausting the nonce space",
+            searched,
+            source.dispatches
+        ));
+    }
+    if discarded > 0 {
+        return Err(anyhow!(
+            "Nonce space exhausted, but the hits of {} dispatches failed verification",
+            discarded
+        ));
+    }
+
+    println!("Result:");
+    println!(
+        "  no solution: all {} nonces searched",
+        searched_hashes.to_formatted_string(&Locale::en)
+    );
+    println!(
+        "  preparation time: {:?}",
+        compute_start.duration_since(program_start)
+    );
+    println!("  computation elapsed: {:?}", compute_start.elapsed());
+    exit(1);
+}
+
+/// CPU reference implementation of the kernel's search.
+mod cpu {
+    use crate::{add_big_int, Algorithm, Hit, Message, Scheme, SearchResult, Target, SHA256_BYTES};
+    use sha1::Sha1;
+    use sha2::{Digest, Sha256, Sha512};
+    use sha3::{Keccak256, Sha3_256};
+
+    pub fn sha256(input: &[u8]) -> [u8; SHA256_BYTES] {
+        Sha256::digest(input).into()
+    }
+
+    /// The RustCrypto implementation of `algorithm`.
+    pub fn hash(algorithm: Algorithm, input: &[u8]) -> Vec<u8> {
+        match algorithm {
+            Algorithm::Sha256 => Sha256::digest(input).to_vec(),
+            Algorithm::Sha1 => Sha1::digest(input).to_vec(),
+            Algorithm::Sha512 => Sha512::digest(input).to_vec(),
+            Algorithm::Sha3_256 => Sha3_256::digest(input).to_vec(),
+            Algorithm::Keccak256 => Keccak256::digest(input).to_vec(),
+        }
+    }
+
+    /// Searches nonces `start + [0, count)` with the same little-endian increment the kernel
+    /// uses and returns every hit. Like the kernel, the constant prefix blocks are hashed once.
+    pub fn search(
+        message: &Message,
+        start: &[u8],
+        count: u32,
+        scheme: &Scheme,
+        target: &Target,
+        threads: usize,
+    ) -> SearchResult {
+        let search = match scheme.algorithm {
+            Algorithm::Sha256 => search_with::<Sha256>,
+            Algorithm::Sha1 => search_with::<Sha1>,
+            Algorithm::Sha512 => search_with::<Sha512>,
+            Algorithm::Sha3_256 => search_with::<Sha3_256>,
+            Algorithm::Keccak256 => search_with::<Keccak256>,
+        };
+        search(message, start, count, scheme, target, threads)
+    }
+
+    fn search_with<D: Digest + Clone + Sync>(
+        message: &Message,
+        start: &[u8],
+        count: u32,
+        scheme: &Scheme,
+        target: &Target,
+        threads: usize,
+    ) -> SearchResult {
+        let prefix_len = message.prefix_len();
+        let mut prefix = D::new();
+        prefix.update(&message.bytes[..prefix_len]);
+        let threads = threads.max(1) as u32;
+        let chunk = count.div_ceil(threads);
+        std::thread::scope(|s| {
+            let handles = (0..threads)
+                .map(|t| {
+                    let first = t * chunk;
+                    let len = chunk.min(count.saturating_sub(first));
+                    let prefix = &prefix;
+                    s.spawn(move || {
+                        let mut hits = Vec::new();
+                        let mut tail = message.with_nonce(start).split_off(prefix_len);
+                        let nonce_start = message.nonce_offset - prefix_len;
+                        let nonce = nonce_start..nonce_start + message.nonce_width;
+                        add_big_int(&mut tail[nonce.clone()], first as u64);
+                        for offset in first..first + len {
+                            let mut hasher = prefix.clone();
+                            hasher.update(&tail);
+                            let mut hash = hasher.finalize().to_vec();
+                            if scheme.double_sha256 {
+                                hash = sha256(&hash).to_vec();
+                            }
+                            if scheme.is_hit(&hash, target) {
+                                hits.push(Hit {
+                                    offset,
+                                    nonce: tail[nonce.clone()].to_vec(),
+                                    hash,
+                                });
+                            }
+                            add_big_int(&mut tail[nonce.clone()], 1);
+                        }
+                        hits
+                    })
+                })
+                .collect::<Vec<_>>();
+            let hits = handles
+                .into_iter()
+                .flat_map(|x| x.join().unwrap())
+                .collect::<Vec<_>>();
+            let found = hits.len() as u32;
+            SearchResult::new(hits, found, false)
+        })
+    }
+}
+
+/// Bitcoin block headers.
+mod bitcoin {
+    use crate::{add_big_int, cpu, BitcoinArgs, Message, SHA256_BYTES};
+    use anyhow::anyhow;
+
+    pub const HEADER_BYTES: usize = 80;
+    pub const MERKLE_ROOT_OFFSET: usize = 36;
+    pub const BITS_OFFSET: usize = 72;
+    pub const NONCE_OFFSET: usize = 76;
+    pub const NONCE_WIDTH: usize = 4;
+
+    pub fn header_from_args(args: &BitcoinArgs) -> anyhow::Result<Vec<u8>> {
+        if let Some(header) = &args.header {
+            let header = hex::decode(header)?;
+            if header.len() != HEADER_BYTES {
+                return Err(anyhow!("A block header must be {} bytes", HEADER_BYTES));
+            }
+            return Ok(header);
+        }
+
+        // clap makes sure the required fields come together.
+        let merkle_root = match (&args.merkle_root, &args.coinbase) {
+            (Some(x), _) => reversed_hash(x)?,
+            // Filled in from the coinbase.
+            (None, Some(_)) => [0; SHA256_BYTES],
+            (None, None) => return Err(anyhow!("`--merkle-root` or `--coinbase` is required")),
+        };
+        let bits = u32::from_str_radix(args.bits.as_deref().unwrap_or_default(), 16)
+            .map_err(|e| anyhow!("Invalid `--bits`: {}", e))?;
+        let mut header = Vec::with_capacity(HEADER_BYTES);
+        header.extend(args.block_version.unwrap_or_default().to_le_bytes());
+        header.extend(reversed_hash(
+            args.prev_block.as_deref().unwrap_or_default(),
+        )?);
+        header.extend(merkle_root);
+        header.extend(args.block_time.unwrap_or_default().to_le_bytes());
+        header.extend(bits.to_le_bytes());
+        header.extend([0; NONCE_WIDTH]);
+        Ok(header)
+    }
+
+    /// Parses a hash in the usual byte reversed hex into internal byte order.
+    fn reversed_hash(hex: &str) -> anyhow::Result<[u8; SHA256_BYTES]> {
+        let mut hash: [u8; SHA256_BYTES] = hex::decode(hex)?
+            .try_into()
+            .map_err(|_| anyhow!("A hash must be {} bytes", SHA256_BYTES))?;
+        hash.reverse();
+        Ok(hash)
+    }
+
+    pub fn bits(header: &[u8]) -> u32 {
+        u32::from_le_bytes(header[BITS_OFFSET..][..4].try_into().unwrap())
+    }
+
+    /// Decodes the compact nBits encoding into a big-endian 256-bit target.
+    pub fn decode_bits(bits: u32) -> anyhow::Result<[u8; SHA256_BYTES]> {
+        let exponent = (bits >> 24) as usize;
+        let mantissa = bits & 0x007f_ffff;
+        if bits & 0x0080_0000 != 0 && mantissa != 0 {
+            return Err(anyhow!("nBits {:08x} encodes a negative target", bits));
+        }
+        let mut target = [0_u8; SHA256_BYTES];
+        if exponent <= 3 {
+            let value = mantissa >> (8 * (3 - exponent));
+            target[SHA256_BYTES - 4..].copy_from_slice(&value.to_be_bytes());
+            return Ok(target);
+        }
+        for (i, byte) in mantissa.to_be_bytes()[1..].iter().enumerate() {
+            // Byte `i` of the mantissa is worth 256^(exponent - 1 - i).
+            let Some(position) = (SHA256_BYTES + i).checked_sub(exponent) else {
+                if *byte != 0 {
+                    return Err(anyhow!("nBits {:08x} overflows 256 bits", bits));
+                }
+                continue;
+            };
+            target[position] = *byte;
+        }
+        Ok(target)
+    }
+
+    /// A coinbase transaction with an extranonce, and the merkle branch linking it to the
+    /// header's merkle root.
+    pub struct Coinbase {
+        bytes: Vec<u8>,
+        extranonce_offset: usize,
+        extranonce_width: usize,
+        branch: Vec<[u8; SHA256_BYTES]>,
+    }
+
+    impl Coinbase {
+        pub fn from_args(args: &BitcoinArgs) -> anyhow::Result<Option<Self>> {
+            let Some(coinbase) = &args.coinbase else {
+                return Ok(None);
+            };
+            let bytes = hex::decode(coinbase)?;
+            // clap makes sure the offset comes with the coinbase.
+            let extranonce_offset = args.extranonce_offset.unwrap_or_default();
+            let extranonce_width = args.extranonce_width;
+            if extranonce_width == 0 || extranonce_offset + extranonce_width > bytes.len() {
+                return Err(anyhow!(
+                    "The extranonce ({} bytes at {}) must be non-empty and lie in the {}-byte \
+                     coinbase",
+                    extranonce_width,
+                    extranonce_offset,
+                    bytes.len()
+                ));
+            }
+            let branch = args
+                .merkle_branch
+                .iter()
+                .map(|x| {
+                    hex::decode(x)?
+                        .try_into()
+                        .map_err(|_| anyhow!("A merkle branch hash must be {} bytes", SHA256_BYTES))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            Ok(Some(Self {
+                bytes,
+                extranonce_offset,
+                extranonce_width,
+                branch,
+            }))
+        }
+
+        /// Offset and width of the extranonce.
+        pub fn extranonce_field(&self) -> (usize, usize) {
+            (self.extranonce_offset, self.extranonce_width)
+        }
+
+        pub fn extranonce(&self) -> &[u8] {
+            &self.bytes[self.extranonce_offset..][..self.extranonce_width]
+        }
+
+        /// Increments the extranonce.
+        pub fn roll(&mut self) {
+            add_big_int(
+                &mut self.bytes[self.extranonce_offset..][..self.extranonce_width],
+                1,
+            );
+        }
+
+        pub fn merkle_root(&self) -> [u8; SHA256_BYTES] {
+            let mut root = sha256d(&self.bytes);
+            for hash in &self.branch {
+                root = sha256d(&[root, *hash].concat());
+            }
+            root
+        }
+
+        /// `header` with this coinbase's merkle root, and the nonce field at its start value.
+        pub fn apply(&self, header: &Message) -> Message {
+            let mut bytes = header.bytes.clone();
+            bytes[MERKLE_ROOT_OFFSET..][..SHA256_BYTES].copy_from_slice(&self.merkle_root());
+            Message::new(
+                bytes,
+                header.nonce_offset,
+                header.nonce_width,
+                header.algorithm,
+            )
+        }
+    }
+
+    fn sha256d(data: &[u8]) -> [u8; SHA256_BYTES] {
+        cpu::sha256(&cpu::sha256(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bitcoin, Args, Message, Target, Work, WorkSource};
+    use clap::Parser;
+
+    /// Bitcoin's difficulty 1 target.
+    const DIFFICULTY_1: &str = "00000000ffff0000000000000000000000000000000000000000000000000000";
+
+    /// The nonce range of a search with these command line arguments.
+    fn range(args: &[&str]) -> anyhow::Result<Option<u64>> {
+        let args = Args::try_parse_from(["sha256-miner"].iter().chain(args))?;
+        Message::from_args(&args)?.range_from_args(&args)
+    }
+
+    /// The `WorkSource` `main` builds for these command line arguments.
+    fn work_source(args: &[&str], hashes_per_dispatch: u32) -> WorkSource {
+        let args = Args::try_parse_from(["sha256-miner"].iter().chain(args)).unwrap();
+        let message = Message::from_args(&args).unwrap();
+        let range = message.range_from_args(&args).unwrap();
+        let coinbase = bitcoin::Coinbase::from_args(&args.bitcoin).unwrap();
+        let message = match &coinbase {
+            Some(coinbase) => coinbase.apply(&message),
+            None => message,
+        };
+        WorkSource::new(
+            message,
+            Target::zero(32),
+            hashes_per_dispatch,
+            coinbase,
+            range,
+        )
+    }
+
+    /// Checks that skipping `n` dispatches and resuming at `position(n)` continues exactly where
+    /// handing out `n` dispatches one by one does, for each `n` below `dispatches`.
+    fn check_skip(args: &[&str], hashes_per_dispatch: u32, dispatches: u64) {
+        let key = |x: Work| (x.index, x.start, x.count, x.message.bytes.clone());
+        let mut fresh = work_source(args, hashes_per_dispatch);
+        for n in 0..dispatches {
+            let expected = fresh.next().map(key);
+            let mut skipped = work_source(args, hashes_per_dispatch);
+            skipped.skip(n);
+            let (nonce, extranonce) = skipped.position(n);
+            assert_eq!(skipped.next().map(key), expected, "dispatch {}", n);
+            if let Some((_, start, _, _)) = expected {
+                assert_eq!(nonce, start, "dispatch {}", n);
+            }
+            let rolled = fresh.coinbase.as_ref().map(|x| x.extranonce().to_vec());
+            assert_eq!(extranonce, rolled, "dispatch {}", n);
+        }
+    }
+
+    #[test]
+    fn decode_bits() {
+        assert_eq!(
+            hex::encode(bitcoin::decode_bits(0x1d00ffff).unwrap()),
+            DIFFICULTY_1
+        );
+        // The mantissa is shifted right for exponents below 3.
+        let target = bitcoin::decode_bits(0x02123456).unwrap();
+        assert_eq!(hex::encode(&target[28..]), "00001234");
+        assert!(target[..28].iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    fn decode_bits_rejects_negative_and_overflowing_targets() {
+        assert!(bitcoin::decode_bits(0x1d800001).is_err());
+        assert!(bitcoin::decode_bits(0x23010000).is_err());
+        // The sign bit is ignored for a zero mantissa.
+        assert_eq!(bitcoin::decode_bits(0x1d800000).unwrap(), [0; 32]);
+    }
+
+    #[test]
+    fn target_from_difficulty() {
+        let target = Target::from_difficulty(1.0, 32).unwrap();
+        assert_eq!(hex::encode(&target.0), DIFFICULTY_1);
+        assert_eq!(target.0, bitcoin::decode_bits(0x1d00ffff).unwrap().to_vec());
+        let target = Target::from_difficulty(256.0, 32).unwrap();
+        assert_eq!(hex::encode(&target.0[..8]), "0000000000ffff00");
+        // Every hash is a hit.
+        assert_eq!(
+            Target::from_difficulty(1e-20, 32).unwrap().0,
+            vec![0xff; 32]
+        );
+    }
+
+    #[test]
+    fn target_from_difficulty_rejects_non_positive_difficulties() {
+        for difficulty in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(Target::from_difficulty(difficulty, 32).is_err());
+        }
+    }
+
+    #[test]
+    fn range_from_end_and_count() {
+        assert_eq!(range(&["--message", "00000000"]).unwrap(), None);
+        // `--end` is little-endian, like `--start`.
+        assert_eq!(
+            range(&["--message", "00000000", "--start", "0a", "--end", "0a01"]).unwrap(),
+            Some(0x100)
+        );
+        assert_eq!(
+            range(&["--message", "00000000", "--count", "1000"]).unwrap(),
+            Some(1000)
+        );
+        // A whole 1-byte field.
+        assert_eq!(
+            range(&["--message", "00", "--count", "256"]).unwrap(),
+            Some(256)
+        );
+    }
+
+    #[test]
+    fn range_rejects_end_not_above_start() {
+        let message = ["--message", "00000000", "--start", "0a01"];
+        for end in ["0a01", "0901", "ff"] {
+            assert!(range(&[&message[..], &["--end", end]].concat()).is_err());
+        }
+    }
+
+    #[test]
+    fn range_rejects_count_wider_than_the_field() {
+        assert!(range(&["--message", "00", "--count", "257"]).is_err());
+        assert!(range(&["--message", "0000", "--nonce-width", "1", "--count", "257"]).is_err());
+        assert!(range(&["--message", "00", "--count", "0"]).is_err());
+    }
+
+    #[test]
+    fn work_source_skip_in_a_range() {
+        // Dispatches of 300, 300, 300 and 100 nonces from 0x01ff, then none.
+        let args = [
+            "--message",
+            "0000000000",
+            "--nonce-offset",
+            "1",
+            "--nonce-width",
+            "2",
+            "--start",
+            "ff01",
+            "--count",
+            "1000",
+        ];
+        check_skip(&args, 300, 6);
+        // Past the end, the end of the range.
+        let source = work_source(&args, 300);
+        assert_eq!(source.position(4), (vec![0xe7, 0x05], None));
+        assert_eq!(source.position(5), (vec![0xe7, 0x05], None));
+        let mut source = work_source(&args, 300);
+        source.skip(4);
+        assert!(source.exhausted());
+        assert!(source.next().is_none());
+    }
+
+    #[test]
+    fn work_source_skip_in_a_nonce_field() {
+        // Dispatches of 100, 100 and 56 nonces.
+        check_skip(&["--message", "00"], 100, 5);
+    }
+
+    #[test]
+    fn work_source_skip_rolls_the_extranonce() {
+        // Dispatches of 3 * 2^30 and 2^30 nonces per extranonce, from 0x22ff, which carries.
+        let prev_block = "00".repeat(32);
+        let args = [
+            "--block-version",
+            "1",
+            "--prev-block",
+            &prev_block,
+            "--block-time",
+            "0",
+            "--bits",
+            "1d00ffff",
+            "--coinbase",
+            "00ff223344",
+            "--extranonce-offset",
+            "1",
+            "--extranonce-width",
+            "2",
+        ];
+        check_skip(&args, 3 << 30, 7);
+        let source = work_source(&args, 3 << 30);
+        assert_eq!(
+            source.position(5),
+            (vec![0, 0, 0, 0xc0], Some(vec![0x01, 0x23]))
+        );
     }
 }
@@ -5,12 +5,20 @@
 //!
 //! Update: Even dx12+vkd3d-proton/vkd3d-wine runs faster than the native Vulkan backend!
 
-use crate::render::State;
-use clap::Parser;
+use crate::camera::Camera;
+use crate::capture::Image;
+use crate::render::{Config, State};
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
 use std::env;
+use std::path::PathBuf;
+use std::process::exit;
 use std::sync::Arc;
-use wgpu::{Backends, Instance, InstanceDescriptor, InstanceFlags};
-use wgpu_benchmarks::FpsStat;
+use std::time::Instant;
+use wgpu_benchmarks::{
+    print_adapters, print_histogram, write_report, AdapterArgs, FpsStat, FrameStats, FrameSummary,
+    HistogramBucket, OutputFormat,
+};
 use winit::application::ApplicationHandler;
 use winit::dpi::PhysicalSize;
 use winit::event_loop::ActiveEventLoop;
@@ -20,17 +28,446 @@ use winit::{
     event_loop::{ControlFlow, EventLoop},
 };
 
-#[derive(Default)]
 struct App {
     pub state: Option<State>,
     pub window: Option<Arc<Window>>,
-    pub fps: Option<FpsStat>,
-    pub max_frame: usize,
+    pub session: Session,
 }
 
 #[derive(Parser)]
+#[command(about = "wgpu port of the vsbm raymarching benchmark")]
 struct Args {
+    /// Stop after this many frames, warmup included.
     max_frame: Option<usize>,
+
+    /// Number of frames at the start excluded from the statistics.
+    #[arg(long, default_value_t = 0)]
+    warmup_frames: usize,
+
+    /// Seconds at the start excluded from the statistics. Together with `--warmup-frames`, both
+    /// have to pass.
+    #[arg(long, default_value_t = 0.0)]
+    warmup_secs: f64,
+
+    /// Stop after measuring for this many seconds, warmup excluded.
+    #[arg(long)]
+    duration: Option<f64>,
+
+    /// How the camera moves.
+    #[arg(long, value_enum, default_value_t = CameraMode::Frame)]
+    camera: CameraMode,
+
+    /// Keyframe file for `--camera path`. Each line holds `frame yaw pitch distance`; `#` starts
+    /// a comment.
+    #[arg(long, required_if_eq("camera", "path"))]
+    camera_path: Option<PathBuf>,
+
+    /// Width of the render resolution (and of the initial window).
+    #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u32).range(1..))]
+    width: u32,
+
+    /// Height of the render resolution (and of the initial window).
+    #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u32).range(1..))]
+    height: u32,
+
+    /// Allow resizing the window. The render resolution stays fixed; frames are scaled to fit.
+    #[arg(long)]
+    resizable: bool,
+
+    /// Present mode of the surface. Falls back to `fifo` if the surface doesn't support it.
+    #[arg(long, value_enum, default_value_t = PresentMode::Immediate)]
+    present_mode: PresentMode,
+
+    /// Maximum number of frames queued for presentation (`desired_maximum_frame_latency`).
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+    frame_latency: u32,
+
+    /// Iterations of the fractal distance kernel (`KERNEL_ITERATIONS` in the shader).
+    #[arg(long, default_value_t = 5)]
+    kernel_iterations: u32,
+
+    /// Raymarch steps per ray (`RAYMARCH_STEPS` in the shader).
+    #[arg(long, default_value_t = 1000)]
+    raymarch_steps: u32,
+
+    /// Raymarch step length relative to the camera distance (`STEP_SIZE` in the shader).
+    #[arg(long, default_value_t = 0.002)]
+    step_size: f32,
+
+    /// Normal estimation offset relative to the hit distance (`NORMAL_EPSILON` in the shader).
+    #[arg(long, default_value_t = 0.00025)]
+    epsilon: f32,
+
+    /// Render into an offscreen texture without creating a window.
+    #[arg(long)]
+    headless: bool,
+
+    #[command(flatten)]
+    adapter: AdapterArgs,
+
+    /// The frame (1-based) that `--capture` and `--compare` operate on.
+    #[arg(long, default_value_t = 1)]
+    capture_frame: usize,
+
+    /// Save the captured frame as PNG.
+    #[arg(long)]
+    capture: Option<PathBuf>,
+
+    /// Compare the captured frame against this reference PNG. Exits with 1 on mismatch.
+    #[arg(long)]
+    compare: Option<PathBuf>,
+
+    /// Maximum allowed per-channel difference for a pixel to still count as matching.
+    #[arg(long, default_value_t = 2)]
+    tolerance: u8,
+
+    /// Where to write the diff image of `--compare`.
+    #[arg(long, default_value = "diff.png")]
+    diff_output: PathBuf,
+
+    /// Measure the GPU time of the render pass with timestamp queries, if the adapter supports it.
+    #[arg(long)]
+    gpu_timestamps: bool,
+
+    /// Write the frame statistics of the run to this file at exit.
+    #[arg(long)]
+    stats_output: Option<PathBuf>,
+
+    /// Format of `--stats-output`.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    stats_format: OutputFormat,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum CameraMode {
+    /// Orbit by a fixed angle per frame, so the animation speed depends on the frame rate.
+    Frame,
+    /// Orbit by a fixed angle per second.
+    Realtime,
+    /// Follow the keyframes of `--camera-path`, interpolated per frame.
+    Path,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum PresentMode {
+    Immediate,
+    Fifo,
+    FifoRelaxed,
+    Mailbox,
+    AutoVsync,
+    AutoNoVsync,
+}
+
+impl From<PresentMode> for wgpu::PresentMode {
+    fn from(value: PresentMode) -> Self {
+        match value {
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::FifoRelaxed => wgpu::PresentMode::FifoRelaxed,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::AutoVsync => wgpu::PresentMode::AutoVsync,
+            PresentMode::AutoNoVsync => wgpu::PresentMode::AutoNoVsync,
+        }
+    }
+}
+
+/// The parameters the results depend on.
+#[derive(Serialize)]
+struct Setup {
+    adapter: String,
+    backend: String,
+    driver: String,
+    width: u32,
+    height: u32,
+    /// `None` when headless.
+    present_mode: Option<String>,
+    frame_latency: u32,
+    kernel_iterations: u32,
+    raymarch_steps: u32,
+    step_size: f32,
+    epsilon: f32,
+    warmup_frames: usize,
+    warmup_secs: f64,
+    duration_secs: Option<f64>,
+    camera: CameraMode,
+    camera_path: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    setup: &'a Setup,
+    /// Wall-clock time of the measured frames.
+    measured_secs: f64,
+    frame_time: FrameSummary,
+    gpu_time: Option<FrameSummary>,
+    histogram: Vec<HistogramBucket>,
+    frame_times_ms: &'a [f64],
+    gpu_times_ms: &'a [f64],
+}
+
+const HISTOGRAM_BUCKETS: usize = 20;
+
+/// Per-run bookkeeping shared by the windowed and the headless loop.
+struct Session {
+    args: Args,
+    setup: Option<Setup>,
+    camera: Camera,
+    /// Number of frames rendered so far.
+    frames: usize,
+    fps: Option<FpsStat>,
+    /// GPU render pass durations (in ms) since the last FPS report.
+    gpu_times: Vec<f64>,
+    /// Start of the first frame.
+    started: Option<Instant>,
+    /// Start of the first frame after the warmup.
+    measure_start: Option<Instant>,
+    /// End of the previous frame; the first frame counts from its start.
+    last_frame_end: Option<Instant>,
+    frame_stats: FrameStats,
+    gpu_stats: FrameStats,
+    /// Set once the `--capture-frame` frame was read back.
+    captured: bool,
+    /// Set when the run failed, e.g. the golden-image comparison.
+    failed: bool,
+}
+
+impl Session {
+    fn new(args: Args, camera: Camera) -> Self {
+        Self {
+            args,
+            setup: None,
+            camera,
+            frames: 0,
+            fps: None,
+            gpu_times: Vec::new(),
+            started: None,
+            measure_start: None,
+            last_frame_end: None,
+            frame_stats: FrameStats::default(),
+            gpu_stats: FrameStats::default(),
+            captured: false,
+            failed: false,
+        }
+    }
+
+    fn render_config(&self) -> Config {
+        Config {
+            size: (self.args.width, self.args.height),
+            kernel_iterations: self.args.kernel_iterations,
+            raymarch_steps: self.args.raymarch_steps,
+            step_size: self.args.step_size,
+            epsilon: self.args.epsilon,
+            gpu_timestamps: self.args.gpu_timestamps,
+            present_mode: self.args.present_mode.into(),
+            frame_latency: self.args.frame_latency,
+        }
+    }
+
+    /// Records and prints the parameters the results depend on.
+    fn start(&mut self, state: &State) {
+        let info = state.adapter_info();
+        let setup = Setup {
+            adapter: info.name.clone(),
+            backend: info.backend.to_string(),
+            driver: format!("{} {}", info.driver, info.driver_info),
+            width: state.size.0,
+            height: state.size.1,
+            present_mode: state.present_mode().map(|x| format!("{:?}", x)),
+            frame_latency: self.args.frame_latency,
+            kernel_iterations: self.args.kernel_iterations,
+            raymarch_steps: self.args.raymarch_steps,
+            step_size: self.args.step_size,
+            epsilon: self.args.epsilon,
+            warmup_frames: self.args.warmup_frames,
+            warmup_secs: self.args.warmup_secs,
+            duration_secs: self.args.duration,
+            camera: self.args.camera,
+            camera_path: self.args.camera_path.clone(),
+        };
+
+        println!("Render resolution: {}x{}", setup.width, setup.height);
+        match &setup.present_mode {
+            Some(mode) => println!(
+                "Present mode: {}, frame latency: {}",
+                mode, setup.frame_latency
+            ),
+            None => println!("Present mode: none (headless)"),
+        }
+        println!(
+            "Kernel iterations: {}, raymarch steps: {}, step size: {}, epsilon: {}",
+            setup.kernel_iterations, setup.raymarch_steps, setup.step_size, setup.epsilon
+        );
+        self.setup = Some(setup);
+    }
+
+    /// Prints the statistics of the run and writes them to `--stats-output`.
+    fn finish(&mut self) {
+        // A golden-image check that never compared anything must not pass.
+        if self.wants_capture() && !self.captured {
+            eprintln!(
+                "Frame {} was never captured, so nothing was saved or compared",
+                self.args.capture_frame
+            );
+            self.failed = true;
+        }
+        let Some(frame_time) = self.frame_stats.summary() else {
+            println!("No frames measured");
+            return;
+        };
+        let measured_secs = match (self.measure_start, self.last_frame_end) {
+            (Some(start), Some(end)) => (end - start).as_secs_f64(),
+            _ => 0.0,
+        };
+        println!(
+            "Measured {} frames in {:.3} s, {} warmup frames excluded",
+            frame_time.frames,
+            measured_secs,
+            self.frames - frame_time.frames
+        );
+        let histogram = self.frame_stats.histogram(HISTOGRAM_BUCKETS);
+        println!("Frame time:");
+        frame_time.print();
+        print_histogram(&histogram);
+        let gpu_time = self.gpu_stats.summary();
+        if let Some(gpu_time) = &gpu_time {
+            println!("GPU time:");
+            gpu_time.print();
+        }
+
+        let (Some(path), Some(setup)) = (&self.args.stats_output, &self.setup) else {
+            return;
+        };
+        let report = Report {
+            setup,
+            measured_secs,
+            frame_time,
+            gpu_time,
+            histogram,
+            frame_times_ms: self.frame_stats.frame_times(),
+            gpu_times_ms: self.gpu_stats.frame_times(),
+        };
+        if let Err(e) = write_report(path, self.args.stats_format, &report) {
+            eprintln!("Writing the statistics failed: {:?}", e);
+            self.failed = true;
+        }
+    }
+
+    fn max_frame(&self) -> usize {
+        self.args.max_frame.unwrap_or(usize::MAX)
+    }
+
+    fn wants_capture(&self) -> bool {
+        self.args.capture.is_some() || self.args.compare.is_some()
+    }
+
+    fn before_frame(&mut self, state: &mut State) {
+        let now = Instant::now();
+        let started = *self.started.get_or_insert(now);
+        if self.last_frame_end.is_none() {
+            self.last_frame_end = Some(now);
+        }
+        if self.measure_start.is_none()
+            && self.frames >= self.args.warmup_frames
+            && (now - started).as_secs_f64() >= self.args.warmup_secs
+        {
+            if self.frames > 0 {
+                println!("Warmup done after {} frames", self.frames);
+            }
+            self.measure_start = Some(now);
+        }
+        state.set_view(self.camera.next_view(now));
+        if self.wants_capture() && self.frames + 1 == self.args.capture_frame {
+            state.request_capture();
+        }
+    }
+
+    /// Returns `false` when the run should stop.
+    fn after_frame(&mut self, state: &mut State) -> bool {
+        let now = Instant::now();
+        let measuring = self.measure_start.is_some();
+        if let Some(last) = self.last_frame_end.replace(now)
+            && measuring
+        {
+            self.frame_stats.record(now - last);
+        }
+        self.frames += 1;
+        if let Some(image) = state.take_capture() {
+            self.captured = true;
+            if let Err(e) = self.handle_capture(&image) {
+                eprintln!("Frame capture failed: {:?}", e);
+                self.failed = true;
+            }
+        }
+
+        for gpu_time in state.drain_gpu_times() {
+            if measuring {
+                self.gpu_stats.record_ms(gpu_time);
+            }
+            self.gpu_times.push(gpu_time);
+        }
+
+        // print the FPS
+        self.report_fps();
+
+        let duration_over = match (self.measure_start, self.args.duration) {
+            (Some(start), Some(duration)) => (now - start).as_secs_f64() >= duration,
+            _ => false,
+        };
+        self.frames < self.max_frame() && !duration_over
+    }
+
+    /// Counts a rendered frame and prints the FPS about once per second.
+    fn report_fps(&mut self) {
+        let Some(f) = &mut self.fps else {
+            self.fps = Some(FpsStat::new());
+            return;
+        };
+        let (d, fps) = f.hint_and_get();
+        if d.as_secs_f64() > 1.0 {
+            if self.gpu_times.is_empty() {
+                println!("FPS: {}", fps);
+            } else {
+                let avg = self.gpu_times.iter().sum::<f64>() / self.gpu_times.len() as f64;
+                println!("FPS: {}, GPU time: {:.3} ms", fps, avg);
+                self.gpu_times.clear();
+            }
+            self.fps = Some(FpsStat::new());
+        }
+    }
+
+    fn handle_capture(&mut self, image: &Image) -> anyhow::Result<()> {
+        if let Some(path) = &self.args.capture {
+            image.save_png(path)?;
+            println!("Frame {} saved to {}", self.frames, path.display());
+        }
+        if let Some(path) = &self.args.compare {
+            let reference = Image::load_png(path)?;
+            let result = image.compare(&reference, self.args.tolerance)?;
+            result.diff.save_png(&self.args.diff_output)?;
+            println!(
+                "Compared frame {} with {}: {} of {} pixels differ (max channel difference: {}), diff saved to {}",
+                self.frames,
+                path.display(),
+                result.mismatched,
+                image.width * image.height,
+                result.max_difference,
+                self.args.diff_output.display()
+            );
+            if result.mismatched > 0 {
+                self.failed = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn exit_code(&self) -> i32 {
+        if self.failed {
+            1
+        } else {
+            0
+        }
+    }
 }
 
 impl ApplicationHandler for App {
@@ -40,25 +477,42 @@ impl ApplicationHandler for App {
             event_loop
                 .create_window(
                     Window::default_attributes()
-                        .with_resizable(false)
-                        .with_inner_size(PhysicalSize::new(1024, 1024)),
+                        .with_resizable(self.session.args.resizable)
+                        .with_inner_size(PhysicalSize::new(
+                            self.session.args.width,
+                            self.session.args.height,
+                        )),
                 )
                 .unwrap(),
         );
 
-        pollster::block_on(async {
-            // let size = window.inner_size();
-            let size = (1024, 1024);
-            let instance = Instance::new(&InstanceDescriptor {
-                backends: Backends::from_env().unwrap_or_default(),
-                flags: InstanceFlags::from_env_or_default(),
-                memory_budget_thresholds: Default::default(),
-                backend_options: Default::default(),
-            });
-            let surface = instance.create_surface(Arc::clone(&window)).unwrap();
-            let state = State::new(instance, surface, size).await;
-            self.state = Some(state);
+        let result = pollster::block_on(async {
+            let size = window.inner_size();
+            let args = &self.session.args.adapter;
+            let instance = args.create_instance()?;
+            let surface = instance.create_surface(Arc::clone(&window))?;
+            let adapter = args.select_adapter(&instance, Some(&surface)).await?;
+            anyhow::Ok(
+                State::new(
+                    adapter,
+                    Some((surface, (size.width, size.height))),
+                    &self.session.render_config(),
+                )
+                .await,
+            )
         });
+        match result {
+            Ok(state) => {
+                self.session.start(&state);
+                self.state = Some(state);
+            }
+            Err(e) => {
+                eprintln!("{:?}", e);
+                self.session.failed = true;
+                event_loop.exit();
+                return;
+            }
+        }
 
         window.request_redraw();
         self.window = Some(window);
@@ -67,39 +521,34 @@ impl ApplicationHandler for App {
     fn window_event(
         &mut self,
         event_loop: &ActiveEventLoop,
-        window_id: WindowId,
+        _window_id: WindowId,
         event: WindowEvent,
     ) {
-        let state = self.state.as_mut().unwrap();
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
-            WindowEvent::Resized(physical_size) => state.resize((1024, 1024)),
+            // a minimized window reports zero size, which can't be configured
+            WindowEvent::Resized(physical_size)
+                if physical_size.width > 0 && physical_size.height > 0 =>
+            {
+                state.resize((physical_size.width, physical_size.height));
+            }
             WindowEvent::RedrawRequested => {
                 let Some(w) = &self.window else {
                     return;
                 };
 
+                self.session.before_frame(state);
                 match state.frame(|| w.pre_present_notify()) {
                     Ok(_) => {}
-                    Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
+                    Err(wgpu::SurfaceError::Lost) => state.configure_surface(),
                     Err(wgpu::SurfaceError::OutOfMemory) => event_loop.exit(),
                     Err(e) => eprintln!("{:?}", e),
                 }
-                self.max_frame -= 1;
-                if self.max_frame == 0 {
+                if !self.session.after_frame(state) {
                     event_loop.exit();
-                    self.max_frame = usize::MAX;
-                }
-
-                // print the FPS
-                if let Some(f) = &mut self.fps {
-                    let (d, fps) = f.hint_and_get();
-                    if d.as_secs_f64() > 1.0 {
-                        println!("FPS: {}", fps);
-                        self.fps = Some(FpsStat::new());
-                    }
-                } else {
-                    self.fps = Some(FpsStat::new());
                 }
 
                 w.request_redraw();
@@ -116,19 +565,355 @@ pub fn main() {
     }
     env_logger::init();
 
-    let event_loop = EventLoop::new().unwrap();
+    if args.adapter.list_adapters {
+        match args.adapter.create_instance() {
+            Ok(instance) => pollster::block_on(print_adapters(&instance)),
+            Err(e) => {
+                eprintln!("{:?}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    if (args.capture.is_some() || args.compare.is_some())
+        && !(1..=args.max_frame.unwrap_or(usize::MAX)).contains(&args.capture_frame)
+    {
+        eprintln!(
+            "`--capture-frame` must be in 1..={}",
+            args.max_frame.unwrap_or(usize::MAX)
+        );
+        exit(1);
+    }
 
-    event_loop.set_control_flow(ControlFlow::Wait);
+    let camera = match Camera::new(args.camera, args.camera_path.as_deref()) {
+        Ok(camera) => camera,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            exit(1);
+        }
+    };
+
+    let headless = args.headless;
+    let session = Session::new(args, camera);
+    let mut session = if headless {
+        run_headless(session)
+    } else {
+        let event_loop = EventLoop::new().unwrap();
+
+        event_loop.set_control_flow(ControlFlow::Wait);
+
+        let mut app = App {
+            state: None,
+            window: None,
+            session,
+        };
+        event_loop.run_app(&mut app).unwrap();
+        app.session
+    };
+    session.finish();
+    exit(session.exit_code());
+}
+
+/// Renders frames into an offscreen texture until the session ends. No window or event loop is
+/// involved.
+fn run_headless(mut session: Session) -> Session {
+    let result = pollster::block_on(async {
+        let instance = session.args.adapter.create_instance()?;
+        let adapter = session.args.adapter.select_adapter(&instance, None).await?;
+        anyhow::Ok(State::new(adapter, None, &session.render_config()).await)
+    });
+    let mut state = match result {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            session.failed = true;
+            return session;
+        }
+    };
+    session.start(&state);
 
-    let mut app = App::default();
-    app.max_frame = args.max_frame.unwrap_or(usize::MAX);
-    event_loop.run_app(&mut app).unwrap();
+    loop {
+        session.before_frame(&mut state);
+        if let Err(e) = state.frame(|| {}) {
+            eprintln!("{:?}", e);
+        }
+        if !session.after_frame(&mut state) {
+            break;
+        }
+    }
+    session
+}
+
+mod camera {
+    use crate::CameraMode;
+    use anyhow::anyhow;
+    use std::path::Path;
+    use std::time::Instant;
+
+    /// Camera placement on a sphere around the origin, looking at the origin.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct View {
+        /// Rotation around the vertical axis, in radians.
+        pub yaw: f32,
+        /// Elevation, in radians.
+        pub pitch: f32,
+        pub distance: f32,
+    }
+
+    impl View {
+        fn lerp(self, other: View, t: f32) -> View {
+            View {
+                yaw: self.yaw + (other.yaw - self.yaw) * t,
+                pitch: self.pitch + (other.pitch - self.pitch) * t,
+                distance: self.distance + (other.distance - self.distance) * t,
+            }
+        }
+    }
+
+    pub struct Keyframe {
+        pub frame: usize,
+        pub view: View,
+    }
+
+    /// Where the orbiting camera starts.
+    const ORBIT_START: View = View {
+        yaw: 2.8,
+        pitch: 0.4,
+        distance: 1.6,
+    };
+    /// Orbit speed in radians per frame (frame-locked) or per second (real-time).
+    const ORBIT_SPEED_PER_FRAME: f32 = 0.012 * 0.5;
+    const ORBIT_SPEED_PER_SEC: f32 = 0.5;
+
+    pub enum Camera {
+        FrameLocked {
+            frame: usize,
+        },
+        Realtime {
+            start: Option<Instant>,
+        },
+        Path {
+            keyframes: Vec<Keyframe>,
+            frame: usize,
+        },
+    }
+
+    impl Camera {
+        pub fn new(mode: CameraMode, path: Option<&Path>) -> anyhow::Result<Self> {
+            Ok(match mode {
+                CameraMode::Frame => Camera::FrameLocked { frame: 0 },
+                CameraMode::Realtime => Camera::Realtime { start: None },
+                CameraMode::Path => {
+                    let path =
+                        path.ok_or_else(|| anyhow!("`--camera path` needs a keyframe file"))?;
+                    Camera::Path {
+                        keyframes: load_keyframes(path)?,
+                        frame: 0,
+                    }
+                }
+            })
+        }
+
+        /// Advances the camera to the next frame, which starts at `now`.
+        pub fn next_view(&mut self, now: Instant) -> View {
+            match self {
+                Camera::FrameLocked { frame } => {
+                    *frame += 1;
+                    View {
+                        yaw: ORBIT_START.yaw + *frame as f32 * ORBIT_SPEED_PER_FRAME,
+                        ..ORBIT_START
+                    }
+                }
+                Camera::Realtime { start } => {
+                    let secs = (now - *start.get_or_insert(now)).as_secs_f32();
+                    View {
+                        yaw: ORBIT_START.yaw + secs * ORBIT_SPEED_PER_SEC,
+                        ..ORBIT_START
+                    }
+                }
+                Camera::Path { keyframes, frame } => {
+                    let view = interpolate(keyframes, *frame);
+                    *frame += 1;
+                    view
+                }
+            }
+        }
+    }
+
+    /// Linear interpolation between the surrounding keyframes; held constant outside of them.
+    fn interpolate(keyframes: &[Keyframe], frame: usize) -> View {
+        let next = keyframes.partition_point(|x| x.frame <= frame);
+        if next == 0 {
+            return keyframes[0].view;
+        }
+        if next == keyframes.len() {
+            return keyframes[next - 1].view;
+        }
+        let (a, b) = (&keyframes[next - 1], &keyframes[next]);
+        let t = (frame - a.frame) as f32 / (b.frame - a.frame) as f32;
+        a.view.lerp(b.view, t)
+    }
+
+    fn load_keyframes(path: &Path) -> anyhow::Result<Vec<Keyframe>> {
+        let content = std::fs::read_to_string(path)?;
+        let mut keyframes = Vec::<Keyframe>::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || {
+                anyhow!(
+                    "{}:{}: expected `frame yaw pitch distance`",
+                    path.display(),
+                    i + 1
+                )
+            };
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let [frame, yaw, pitch, distance] = fields[..] else {
+                return Err(error());
+            };
+            let keyframe = Keyframe {
+                frame: frame.parse().map_err(|_| error())?,
+                view: View {
+                    yaw: yaw.parse().map_err(|_| error())?,
+                    pitch: pitch.parse().map_err(|_| error())?,
+                    distance: distance.parse().map_err(|_| error())?,
+                },
+            };
+            if let Some(last) = keyframes.last()
+                && last.frame >= keyframe.frame
+            {
+                return Err(anyhow!(
+                    "{}:{}: keyframes must be in increasing frame order",
+                    path.display(),
+                    i + 1
+                ));
+            }
+            keyframes.push(keyframe);
+        }
+        if keyframes.is_empty() {
+            return Err(anyhow!("{}: no keyframes", path.display()));
+        }
+        Ok(keyframes)
+    }
+}
+
+mod capture {
+    use anyhow::anyhow;
+    use std::fs::File;
+    use std::io::{BufReader, BufWriter};
+    use std::path::Path;
+
+    /// A tightly packed RGBA8 image.
+    pub struct Image {
+        pub width: u32,
+        pub height: u32,
+        pub rgba: Vec<u8>,
+    }
+
+    pub struct Comparison {
+        /// Number of pixels with a channel differing by more than the tolerance.
+        pub mismatched: usize,
+        pub max_difference: u8,
+        /// Mismatched pixels in red, the others as a dimmed grayscale of the reference.
+        pub diff: Image,
+    }
+
+    impl Image {
+        pub fn save_png(&self, path: &Path) -> anyhow::Result<()> {
+            let writer = BufWriter::new(File::create(path)?);
+            let mut encoder = png::Encoder::new(writer, self.width, self.height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&self.rgba)?;
+            writer.finish()?;
+            Ok(())
+        }
+
+        pub fn load_png(path: &Path) -> anyhow::Result<Self> {
+            let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+            decoder.set_transformations(png::Transformations::normalize_to_color8());
+            let mut reader = decoder.read_info()?;
+            let mut buf = vec![0_u8; reader.output_buffer_size().unwrap_or_default()];
+            let info = reader.next_frame(&mut buf)?;
+            let buf = &buf[..info.buffer_size()];
+
+            let rgba = match info.color_type {
+                png::ColorType::Rgba => buf.to_vec(),
+                png::ColorType::Rgb => buf
+                    .chunks_exact(3)
+                    .flat_map(|x| [x[0], x[1], x[2], 255])
+                    .collect(),
+                png::ColorType::GrayscaleAlpha => buf
+                    .chunks_exact(2)
+                    .flat_map(|x| [x[0], x[0], x[0], x[1]])
+                    .collect(),
+                png::ColorType::Grayscale => buf.iter().flat_map(|&x| [x, x, x, 255]).collect(),
+                png::ColorType::Indexed => {
+                    return Err(anyhow!("Unexpected indexed color after expansion"));
+                }
+            };
+            Ok(Self {
+                width: info.width,
+                height: info.height,
+                rgba,
+            })
+        }
+
+        pub fn compare(&self, reference: &Image, tolerance: u8) -> anyhow::Result<Comparison> {
+            if (self.width, self.height) != (reference.width, reference.height) {
+                return Err(anyhow!(
+                    "Size mismatch: frame is {}x{}, reference is {}x{}",
+                    self.width,
+                    self.height,
+                    reference.width,
+                    reference.height
+                ));
+            }
+
+            let mut mismatched = 0;
+            let mut max_difference = 0;
+            let mut diff = Vec::with_capacity(self.rgba.len());
+            for (a, b) in self
+                .rgba
+                .chunks_exact(4)
+                .zip(reference.rgba.chunks_exact(4))
+            {
+                let difference = a.iter().zip(b).map(|(x, y)| x.abs_diff(*y)).max().unwrap();
+                max_difference = max_difference.max(difference);
+                if difference > tolerance {
+                    mismatched += 1;
+                    diff.extend_from_slice(&[255, 0, 0, 255]);
+                } else {
+                    let luma = ((b[0] as u32 + b[1] as u32 + b[2] as u32) / 3 / 4) as u8;
+                    diff.extend_from_slice(&[luma, luma, luma, 255]);
+                }
+            }
+
+            Ok(Comparison {
+                mismatched,
+                max_difference,
+                diff: Image {
+                    width: self.width,
+                    height: self.height,
+                    rgba: diff,
+                },
+            })
+        }
+    }
 }
 
 mod render {
+    use crate::camera::View;
+    use crate::capture::Image;
     use bytemuck::{bytes_of, Pod, Zeroable};
+    use std::sync::mpsc;
+    use wgpu::wgt::PollType;
     use wgpu::{
-        include_wgsl, Instance, LoadOpDontCare, PipelineCompilationOptions, Surface, TextureFormat,
+        include_wgsl, Adapter, LoadOpDontCare, PipelineCompilationOptions, Surface, TextureFormat,
     };
     use wgpu_benchmarks::default;
 
@@ -148,51 +933,333 @@ mod render {
         padding5: f32,
     }
 
+    fn create_offscreen_texture(
+        device: &wgpu::Device,
+        size: (u32, u32),
+        format: TextureFormat,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Target"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+    }
+
+    /// Draws the offscreen frame onto the surface, scaled to fit and letterboxed.
+    struct Scaler {
+        pipeline: wgpu::RenderPipeline,
+        bind_group: wgpu::BindGroup,
+    }
+
+    impl Scaler {
+        fn new(device: &wgpu::Device, format: TextureFormat, source: &wgpu::Texture) -> Self {
+            let shader = device.create_shader_module(include_wgsl!("../blit.wgsl"));
+            let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Scaler Pipeline"),
+                layout: None,
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(format.into())],
+                }),
+                multiview_mask: None,
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                cache: None,
+            });
+            let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Scaler Sampler"),
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..default!()
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Scaler Bind Group"),
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(
+                            &source.create_view(&default!()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                ],
+            });
+            Self {
+                pipeline,
+                bind_group,
+            }
+        }
+
+        fn encode(
+            &self,
+            encoder: &mut wgpu::CommandEncoder,
+            target: &wgpu::TextureView,
+            target_size: (u32, u32),
+            source_size: (u32, u32),
+        ) {
+            let (tw, th) = (target_size.0 as f32, target_size.1 as f32);
+            let scale = (tw / source_size.0 as f32).min(th / source_size.1 as f32);
+            let (w, h) = (source_size.0 as f32 * scale, source_size.1 as f32 * scale);
+
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Scaler Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+                multiview_mask: None,
+            });
+            pass.set_viewport((tw - w) / 2.0, (th - h) / 2.0, w, h, 0.0, 1.0);
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+
     pub struct State {
-        surface: wgpu::Surface<'static>,
+        /// `None` when rendering headless.
+        surface: Option<wgpu::Surface<'static>>,
+        surface_size: (u32, u32),
+        /// Usages of the surface; `COPY_SRC` is needed for frame capture.
+        surface_usage: wgpu::TextureUsages,
+        adapter_info: wgpu::AdapterInfo,
+        /// The present mode actually in use.
+        present_mode: wgpu::PresentMode,
+        frame_latency: u32,
+        /// Render target used when there's no surface, or the surface size differs from `size`.
+        offscreen: Option<wgpu::Texture>,
+        scaler: Option<Scaler>,
         device: wgpu::Device,
         queue: wgpu::Queue,
+        /// The render resolution.
         pub size: (u32, u32),
         render_pipeline: wgpu::RenderPipeline,
-        elapsed: f32,
+        view: View,
         texture_format: wgpu::TextureFormat,
         immediates: Immediates,
+        capture_requested: bool,
+        captured: Option<Image>,
+        gpu_timer: Option<GpuTimer>,
     }
 
     pub struct Config {
+        /// The render resolution; independent of the window size.
+        pub size: (u32, u32),
         pub kernel_iterations: u32,
+        pub raymarch_steps: u32,
+        pub step_size: f32,
+        pub epsilon: f32,
+        /// Record timestamps around the render pass. Ignored if the adapter lacks
+        /// `TIMESTAMP_QUERY`.
+        pub gpu_timestamps: bool,
+        /// Falls back to `Fifo` if the surface doesn't support it.
+        pub present_mode: wgpu::PresentMode,
+        pub frame_latency: u32,
     }
 
     impl Default for Config {
         fn default() -> Self {
             Self {
+                size: (1024, 1024),
                 kernel_iterations: 5,
+                raymarch_steps: 1000,
+                step_size: 0.002,
+                epsilon: 0.00025,
+                gpu_timestamps: false,
+                present_mode: wgpu::PresentMode::Immediate,
+                frame_latency: 2,
+            }
+        }
+    }
+
+    /// Measures render pass durations with timestamp queries.
+    ///
+    /// Every frame resolves its two timestamps into its own mappable buffer, which is read back
+    /// asynchronously so the measurement doesn't stall the frame loop.
+    struct GpuTimer {
+        query_set: wgpu::QuerySet,
+        resolve_buffer: wgpu::Buffer,
+        /// Nanoseconds per timestamp tick.
+        period: f32,
+        readback_buffers: Vec<wgpu::Buffer>,
+        /// Indices of `readback_buffers` not in use.
+        free: Vec<usize>,
+        mapped_tx: mpsc::Sender<usize>,
+        mapped_rx: mpsc::Receiver<usize>,
+        /// Finished measurements in milliseconds.
+        results: Vec<f64>,
+    }
+
+    impl GpuTimer {
+        const QUERY_BUFFER_SIZE: u64 = 2 * wgpu::QUERY_SIZE as u64;
+
+        fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+            let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Timestamp Queries"),
+                ty: wgpu::QueryType::Timestamp,
+                count: 2,
+            });
+            let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Resolve Buffer"),
+                size: Self::QUERY_BUFFER_SIZE,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            let (mapped_tx, mapped_rx) = mpsc::channel();
+            Self {
+                query_set,
+                resolve_buffer,
+                period: queue.get_timestamp_period(),
+                readback_buffers: Vec::new(),
+                free: Vec::new(),
+                mapped_tx,
+                mapped_rx,
+                results: Vec::new(),
             }
         }
+
+        fn timestamp_writes(&self) -> wgpu::RenderPassTimestampWrites<'_> {
+            wgpu::RenderPassTimestampWrites {
+                query_set: &self.query_set,
+                beginning_of_pass_write_index: Some(0),
+                end_of_pass_write_index: Some(1),
+            }
+        }
+
+        /// Resolves the queries into a free readback buffer. Returns its index.
+        fn resolve(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) -> usize {
+            let index = self.free.pop().unwrap_or_else(|| {
+                self.readback_buffers
+                    .push(device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Timestamp Readback Buffer"),
+                        size: Self::QUERY_BUFFER_SIZE,
+                        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }));
+                self.readback_buffers.len() - 1
+            });
+            encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+            encoder.copy_buffer_to_buffer(
+                &self.resolve_buffer,
+                0,
+                &self.readback_buffers[index],
+                0,
+                Self::QUERY_BUFFER_SIZE,
+            );
+            index
+        }
+
+        /// Call after the submission containing [`GpuTimer::resolve`].
+        fn map(&self, index: usize) {
+            let tx = self.mapped_tx.clone();
+            self.readback_buffers[index].map_async(wgpu::MapMode::Read, .., move |r| {
+                r.unwrap();
+                tx.send(index).unwrap();
+            });
+        }
+
+        /// Reads all measurements that are ready.
+        fn collect(&mut self) {
+            while let Ok(index) = self.mapped_rx.try_recv() {
+                let buffer = &self.readback_buffers[index];
+                let ticks: [u64; 2] = bytemuck::pod_read_unaligned(&buffer.get_mapped_range(..));
+                buffer.unmap();
+                self.free.push(index);
+
+                let nanos = ticks[1].wrapping_sub(ticks[0]) as f64 * self.period as f64;
+                self.results.push(nanos / 1_000_000.0);
+            }
+        }
+    }
+
+    /// The `screen_size` immediate: scales the view plane so pixels stay square.
+    fn aspect_scale(size: (u32, u32)) -> [f32; 2] {
+        let (w, h) = (size.0 as f32, size.1 as f32);
+        if w >= h {
+            [w / h, 1.0]
+        } else {
+            [1.0, h / w]
+        }
     }
 
     impl State {
         pub fn configure_surface(&self) {
+            let Some(surface) = &self.surface else {
+                return;
+            };
             let surface_config = wgpu::SurfaceConfiguration {
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                usage: self.surface_usage,
                 format: self.texture_format,
                 view_formats: vec![self.texture_format],
                 alpha_mode: wgpu::CompositeAlphaMode::Auto,
-                width: self.size.0,
-                height: self.size.1,
-                desired_maximum_frame_latency: 2,
-                present_mode: wgpu::PresentMode::Immediate,
+                width: self.surface_size.0,
+                height: self.surface_size.1,
+                desired_maximum_frame_latency: self.frame_latency,
+                present_mode: self.present_mode,
             };
-            self.surface.configure(&self.device, &surface_config);
+            surface.configure(&self.device, &surface_config);
         }
 
-        pub async fn new(instance: Instance, surface: Surface<'static>, size: (u32, u32)) -> Self {
-            let adapter = instance.request_adapter(&default!()).await.unwrap();
+        /// Creates the renderer. `surface` comes with its size. Without it, frames are rendered into
+        /// an offscreen texture.
+        pub async fn new(
+            adapter: Adapter,
+            surface: Option<(Surface<'static>, (u32, u32))>,
+            config: &Config,
+        ) -> Self {
+            let size = config.size;
+            let (surface, surface_size) = match surface {
+                Some((surface, surface_size)) => (Some(surface), surface_size),
+                None => (None, size),
+            };
+            let mut required_features = wgpu::Features::IMMEDIATES;
+            let mut gpu_timestamps = config.gpu_timestamps;
+            if gpu_timestamps && !adapter.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+                log::warn!(
+                    "The adapter doesn't support TIMESTAMP_QUERY; GPU timestamps are disabled"
+                );
+                gpu_timestamps = false;
+            }
+            if gpu_timestamps {
+                required_features |= wgpu::Features::TIMESTAMP_QUERY;
+            }
 
             let (device, queue) = adapter
                 .request_device(&wgpu::DeviceDescriptor {
                     label: None,
-                    required_features: wgpu::Features::IMMEDIATES,
+                    required_features,
                     required_limits: wgpu::Limits {
                         max_immediate_size: 80,
                         ..default!()
@@ -204,14 +1271,35 @@ mod render {
                 .await
                 .unwrap();
 
-            let surface_caps = surface.get_capabilities(&adapter);
-
             // Do not use srgb suffix. This makes wgpu think all colors we give are already in a
             // non-linear sRGB space and do not do an automatic gamma correction.
-            let mut texture_format = TextureFormat::Bgra8Unorm;
-            if !surface_caps.formats.iter().any(|x| x == &texture_format) {
-                texture_format = surface_caps.formats[0].remove_srgb_suffix();
-            }
+            let mut present_mode = config.present_mode;
+            let (texture_format, surface_usage) = match &surface {
+                Some(surface) => {
+                    let surface_caps = surface.get_capabilities(&adapter);
+                    let mut texture_format = TextureFormat::Bgra8Unorm;
+                    if !surface_caps.formats.iter().any(|x| x == &texture_format) {
+                        texture_format = surface_caps.formats[0].remove_srgb_suffix();
+                    }
+                    let usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC);
+                    // the `Auto*` modes resolve to a supported mode by themselves
+                    let is_auto = matches!(
+                        present_mode,
+                        wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync
+                    );
+                    if !is_auto && !surface_caps.present_modes.contains(&present_mode) {
+                        log::warn!(
+                            "Present mode {:?} is not supported (supported: {:?}); falling back to Fifo",
+                            present_mode,
+                            surface_caps.present_modes
+                        );
+                        present_mode = wgpu::PresentMode::Fifo;
+                    }
+                    (texture_format, usage)
+                }
+                None => (TextureFormat::Rgba8Unorm, wgpu::TextureUsages::empty()),
+            };
 
             let shader = device.create_shader_module(include_wgsl!("../vsbm.wgsl"));
             // let shader = device.create_shader_module(include_spirv!("../a.spv"));
@@ -231,18 +1319,22 @@ mod render {
                 label: Some("Render Pipeline"),
                 layout: Some(&render_pipeline_layout),
                 vertex: wgpu::VertexState {
-                    module: &shader_vs,
+                    module: shader_vs,
                     entry_point: Some("vs_main"),
                     compilation_options: Default::default(),
                     buffers: &[],
                 },
                 fragment: Some(wgpu::FragmentState {
-                    module: &shader_fs,
+                    module: shader_fs,
                     entry_point: Some("fs_main"),
                     compilation_options: PipelineCompilationOptions {
                         zero_initialize_workgroup_memory: default!(),
-                        // constants: &[("KERNEL_ITERATIONS", config.kernel_iterations as f64)],
-                        constants: &[],
+                        constants: &[
+                            ("KERNEL_ITERATIONS", config.kernel_iterations as f64),
+                            ("RAYMARCH_STEPS", config.raymarch_steps as f64),
+                            ("STEP_SIZE", config.step_size as f64),
+                            ("NORMAL_EPSILON", config.epsilon as f64),
+                        ],
                     },
                     targets: &[Some(wgpu::ColorTargetState {
                         format: texture_format,
@@ -257,32 +1349,99 @@ mod render {
                 cache: None,
             });
 
-            let state = Self {
+            let gpu_timer = gpu_timestamps.then(|| GpuTimer::new(&device, &queue));
+            let mut state = Self {
                 surface,
+                surface_size,
+                surface_usage,
+                adapter_info: adapter.get_info(),
+                present_mode,
+                frame_latency: config.frame_latency,
+                offscreen: None,
+                scaler: None,
                 device,
                 queue,
                 size,
                 render_pipeline,
-                elapsed: 0f32,
+                view: View {
+                    yaw: 0.0,
+                    pitch: 0.0,
+                    distance: 1.0,
+                },
                 texture_format,
                 immediates: Zeroable::zeroed(),
+                capture_requested: false,
+                captured: None,
+                gpu_timer,
             };
             state.configure_surface();
+            state.prepare_offscreen();
             state
         }
 
-        pub fn resize(&mut self, new_size: (u32, u32)) {
-            self.size = new_size;
+        pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+            &self.adapter_info
+        }
+
+        /// The present mode in use, `None` when headless.
+        pub fn present_mode(&self) -> Option<wgpu::PresentMode> {
+            self.surface.as_ref().map(|_| self.present_mode)
+        }
+
+        /// Whether frames can be rendered straight into the surface.
+        fn renders_to_surface(&self) -> bool {
+            self.surface.is_some() && self.surface_size == self.size
+        }
+
+        /// Creates the offscreen target (and the scaler for it) if it's needed and missing.
+        fn prepare_offscreen(&mut self) {
+            if self.renders_to_surface() || self.offscreen.is_some() {
+                return;
+            }
+            let texture = create_offscreen_texture(&self.device, self.size, self.texture_format);
+            if self.surface.is_some() {
+                self.scaler = Some(Scaler::new(&self.device, self.texture_format, &texture));
+            }
+            self.offscreen = Some(texture);
+        }
+
+        /// Reads back the next rendered frame. Fetch it with [`State::take_capture`].
+        pub fn request_capture(&mut self) {
+            self.capture_requested = true;
+        }
+
+        pub fn take_capture(&mut self) -> Option<Image> {
+            self.captured.take()
+        }
 
-            // reconfigure the surface
+        /// Returns the GPU render pass durations (in ms) measured since the last call, oldest
+        /// first. Always empty if GPU timestamps are disabled.
+        pub fn drain_gpu_times(&mut self) -> Vec<f64> {
+            let Some(timer) = &mut self.gpu_timer else {
+                return Vec::new();
+            };
+            self.device.poll(PollType::Poll).unwrap();
+            timer.collect();
+            std::mem::take(&mut timer.results)
+        }
+
+        /// Reconfigures the surface after the window was resized. The render resolution stays the
+        /// same; frames are scaled if the sizes differ.
+        pub fn resize(&mut self, new_size: (u32, u32)) {
+            self.surface_size = new_size;
             self.configure_surface();
+            self.prepare_offscreen();
+        }
+
+        /// Sets the camera for the following frames.
+        pub fn set_view(&mut self, view: View) {
+            self.view = view;
         }
 
         fn update(&mut self) {
-            self.elapsed += 0.012;
-            let ang1 = 2.8 + self.elapsed * 0.5; // rotation
-            let ang2: f32 = 0.4;
-            let len = 1.6;
+            let ang1 = self.view.yaw;
+            let ang2 = self.view.pitch;
+            let len = self.view.distance;
 
             let origin = [
                 len * ang1.cos() * ang2.cos(),
@@ -310,7 +1469,7 @@ mod render {
                 padding3: 0.0,
                 forward,
                 padding4: 0.0,
-                screen_size: [1.0, 1.0],
+                screen_size: aspect_scale(self.size),
                 len,
                 padding5: 0.0,
             };
@@ -322,14 +1481,20 @@ mod render {
         ) -> Result<(), wgpu::SurfaceError> {
             self.update();
 
-            let surface_texture = self.surface.get_current_texture()?;
+            let surface_texture = match &self.surface {
+                Some(surface) => Some(surface.get_current_texture()?),
+                None => None,
+            };
+            let texture = match (&surface_texture, &self.offscreen) {
+                (Some(surface_texture), _) if self.renders_to_surface() => &surface_texture.texture,
+                (_, Some(offscreen)) => offscreen,
+                _ => unreachable!("the offscreen target is prepared whenever it's needed"),
+            };
 
-            let texture_view = surface_texture
-                .texture
-                .create_view(&wgpu::TextureViewDescriptor {
-                    format: Some(self.texture_format),
-                    ..Default::default()
-                });
+            let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
+                format: Some(self.texture_format),
+                ..Default::default()
+            });
 
             let mut encoder = self
                 .device
@@ -351,7 +1516,7 @@ mod render {
                     })],
                     depth_stencil_attachment: None,
                     occlusion_query_set: None,
-                    timestamp_writes: None,
+                    timestamp_writes: self.gpu_timer.as_ref().map(|x| x.timestamp_writes()),
                     multiview_mask: None,
                 });
 
@@ -359,12 +1524,115 @@ mod render {
                 pass.set_immediates(0, bytes_of(&self.immediates));
                 pass.draw(0..6, 0..1);
             }
+            if let (Some(scaler), Some(surface_texture)) = (&self.scaler, &surface_texture)
+                && !self.renders_to_surface()
+            {
+                let surface_view =
+                    surface_texture
+                        .texture
+                        .create_view(&wgpu::TextureViewDescriptor {
+                            format: Some(self.texture_format),
+                            ..Default::default()
+                        });
+                scaler.encode(&mut encoder, &surface_view, self.surface_size, self.size);
+            }
+            let timer_readback = self
+                .gpu_timer
+                .as_mut()
+                .map(|x| x.resolve(&self.device, &mut encoder));
+            let readback = if self.capture_requested {
+                self.capture_requested = false;
+                self.encode_readback(&mut encoder, texture)
+            } else {
+                None
+            };
             let command_buffer = encoder.finish();
 
             before_submit_callback();
-            self.queue.submit([command_buffer]);
-            surface_texture.present();
+            let submission = self.queue.submit([command_buffer]);
+            if let (Some(timer), Some(index)) = (&self.gpu_timer, timer_readback) {
+                timer.map(index);
+            }
+            if let Some((buffer, padded_bytes_per_row)) = readback {
+                self.captured = Some(self.read_image(&buffer, padded_bytes_per_row));
+            }
+            match surface_texture {
+                Some(surface_texture) => surface_texture.present(),
+                None => {
+                    // Nothing throttles us without a swapchain; wait for the frame to finish.
+                    self.device
+                        .poll(PollType::Wait {
+                            submission_index: Some(submission),
+                            timeout: None,
+                        })
+                        .unwrap();
+                }
+            }
             Ok(())
         }
+
+        /// Copies `texture` into a mappable buffer. Returns the buffer and its padded row size.
+        fn encode_readback(
+            &self,
+            encoder: &mut wgpu::CommandEncoder,
+            texture: &wgpu::Texture,
+        ) -> Option<(wgpu::Buffer, u32)> {
+            if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+                log::error!("The surface does not support COPY_SRC; frame capture is skipped");
+                return None;
+            }
+
+            let unpadded_bytes_per_row = self.size.0 * 4;
+            let padded_bytes_per_row =
+                unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+            let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Readback Buffer"),
+                size: padded_bytes_per_row as u64 * self.size.1 as u64,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            encoder.copy_texture_to_buffer(
+                texture.as_image_copy(),
+                wgpu::TexelCopyBufferInfo {
+                    buffer: &buffer,
+                    layout: wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(padded_bytes_per_row),
+                        rows_per_image: None,
+                    },
+                },
+                texture.size(),
+            );
+            Some((buffer, padded_bytes_per_row))
+        }
+
+        fn read_image(&self, buffer: &wgpu::Buffer, padded_bytes_per_row: u32) -> Image {
+            buffer.map_async(wgpu::MapMode::Read, .., |r| r.unwrap());
+            self.device.poll(PollType::wait_indefinitely()).unwrap();
+
+            let bgra = matches!(
+                self.texture_format,
+                TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+            );
+            let mapped = buffer.get_mapped_range(..);
+            let mut rgba = Vec::with_capacity((self.size.0 * self.size.1 * 4) as usize);
+            for row in mapped.chunks_exact(padded_bytes_per_row as usize) {
+                for pixel in row[..(self.size.0 * 4) as usize].chunks_exact(4) {
+                    if bgra {
+                        rgba.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                    } else {
+                        rgba.extend_from_slice(pixel);
+                    }
+                }
+            }
+            drop(mapped);
+            buffer.unmap();
+
+            Image {
+                width: self.size.0,
+                height: self.size.1,
+                rgba,
+            }
+        }
     }
 }
@@ -0,0 +1,95 @@
+// Keccak-f[1600] sponge with a 1088-bit rate, as SHA3-256 and Keccak-256 use it, for
+// `hash-miner.wgsl`. The two only differ in the padding, which the host does. WGSL has no 64-bit
+// integers, so a lane is a `vec2<u32>` of its low and high halves, which is also how the state
+// and the blocks hold them.
+
+const STATE_WORDS = 50u;
+const BLOCK_WORDS = 34u;
+const DIGEST_WORDS = 8u;
+const BIG_ENDIAN_WORDS = false;
+
+const ROUND_CONSTANTS = array<vec2<u32>, 24>(
+  vec2(0x00000001u, 0x00000000u), vec2(0x00008082u, 0x00000000u), vec2(0x0000808au, 0x80000000u),
+  vec2(0x80008000u, 0x80000000u), vec2(0x0000808bu, 0x00000000u), vec2(0x80000001u, 0x00000000u),
+  vec2(0x80008081u, 0x80000000u), vec2(0x00008009u, 0x80000000u), vec2(0x0000008au, 0x00000000u),
+  vec2(0x00000088u, 0x00000000u), vec2(0x80008009u, 0x00000000u), vec2(0x8000000au, 0x00000000u),
+  vec2(0x8000808bu, 0x00000000u), vec2(0x0000008bu, 0x80000000u), vec2(0x00008089u, 0x80000000u),
+  vec2(0x00008003u, 0x80000000u), vec2(0x00008002u, 0x80000000u), vec2(0x00000080u, 0x80000000u),
+  vec2(0x0000800au, 0x00000000u), vec2(0x8000000au, 0x80000000u), vec2(0x80008081u, 0x80000000u),
+  vec2(0x00008080u, 0x80000000u), vec2(0x80000001u, 0x00000000u), vec2(0x80008008u, 0x80000000u)
+);
+
+// Rotation of lane `x + 5 * y`.
+const RHO = array<u32, 25>(
+  0u, 1u, 62u, 28u, 27u, 36u, 44u, 6u, 55u, 20u, 3u, 10u, 43u, 25u, 39u, 41u, 45u, 15u, 21u, 8u, 18u, 2u, 61u, 56u, 14u
+);
+
+// `b` is in 0..64 but not 32.
+fn ROTLEFT64(a : vec2<u32>, b : u32) -> vec2<u32> {
+  if (b == 0u) {
+    return a;
+  }
+  var x = a;
+  var n = b;
+  if (n > 32u) {
+    x = x.yx;
+    n -= 32u;
+  }
+  return vec2((x.x << n) | (x.y >> (32u - n)), (x.y << n) | (x.x >> (32u - n)));
+}
+
+fn keccak_f(lanes : ptr<function, array<vec2<u32>, 25>>) {
+  var c : array<vec2<u32>, 5>;
+  var b : array<vec2<u32>, 25>;
+  for (var round = 0u; round < 24u; round++) {
+    // θ
+    for (var x = 0u; x < 5u; x++) {
+      c[x] = (*lanes)[x] ^ (*lanes)[x + 5u] ^ (*lanes)[x + 10u] ^ (*lanes)[x + 15u]
+        ^ (*lanes)[x + 20u];
+    }
+    for (var x = 0u; x < 5u; x++) {
+      let d = c[(x + 4u) % 5u] ^ ROTLEFT64(c[(x + 1u) % 5u], 1u);
+      for (var y = 0u; y < 25u; y += 5u) {
+        (*lanes)[x + y] ^= d;
+      }
+    }
+    // ρ and π
+    for (var x = 0u; x < 5u; x++) {
+      for (var y = 0u; y < 5u; y++) {
+        b[y + 5u * ((2u * x + 3u * y) % 5u)] = ROTLEFT64((*lanes)[x + 5u * y], RHO[x + 5u * y]);
+      }
+    }
+    // χ
+    for (var y = 0u; y < 25u; y += 5u) {
+      for (var x = 0u; x < 5u; x++) {
+        (*lanes)[x + y] = b[x + y] ^ (~b[(x + 1u) % 5u + y] & b[(x + 2u) % 5u + y]);
+      }
+    }
+    // ι
+    (*lanes)[0] ^= ROUND_CONSTANTS[round];
+  }
+}
+
+fn compress(state : ptr<function, array<u32, STATE_WORDS>>, block : ptr<function, array<u32, BLOCK_WORDS>>) {
+  var lanes : array<vec2<u32>, 25>;
+  for (var i = 0u; i < 25u; i++) {
+    lanes[i] = vec2((*state)[2u * i], (*state)[2u * i + 1u]);
+  }
+  for (var i = 0u; i < BLOCK_WORDS / 2u; i++) {
+    lanes[i] ^= vec2((*block)[2u * i], (*block)[2u * i + 1u]);
+  }
+  keccak_f(&lanes);
+  for (var i = 0u; i < 25u; i++) {
+    (*state)[2u * i] = lanes[i].x;
+    (*state)[2u * i + 1u] = lanes[i].y;
+  }
+}
+
+// The digest's bytes are the state's, little-endian.
+fn digest(state : ptr<function, array<u32, STATE_WORDS>>) -> array<u32, DIGEST_WORDS> {
+  var hash : array<u32, DIGEST_WORDS>;
+  for (var i = 0u; i < DIGEST_WORDS; i++) {
+    hash[i] = byte_swap((*state)[i]);
+  }
+  return hash;
+}
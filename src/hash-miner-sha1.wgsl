@@ -0,0 +1,55 @@
+// SHA-1 for `hash-miner.wgsl`.
+
+const STATE_WORDS = 5u;
+const BLOCK_WORDS = 16u;
+const DIGEST_WORDS = 5u;
+const BIG_ENDIAN_WORDS = true;
+
+fn ROTLEFT(a : u32, b : u32) -> u32 { return (a << b) | (a >> (32u - b)); }
+
+// The message schedule is kept as a rolling window of 16 words instead of all 80.
+fn compress(state : ptr<function, array<u32, STATE_WORDS>>, block : ptr<function, array<u32, BLOCK_WORDS>>) {
+  var w = *block;
+  var a = (*state)[0];
+  var b = (*state)[1];
+  var c = (*state)[2];
+  var d = (*state)[3];
+  var e = (*state)[4];
+
+  for (var i = 0u; i < 80u; i++) {
+    if (i >= 16u) {
+      w[i & 15u] = ROTLEFT(w[(i - 3u) & 15u] ^ w[(i - 8u) & 15u] ^ w[(i - 14u) & 15u] ^ w[i & 15u], 1u);
+    }
+    var f : u32;
+    var k : u32;
+    if (i < 20u) {
+      f = (b & c) | (~b & d);
+      k = 0x5a827999u;
+    } else if (i < 40u) {
+      f = b ^ c ^ d;
+      k = 0x6ed9eba1u;
+    } else if (i < 60u) {
+      f = (b & c) | (b & d) | (c & d);
+      k = 0x8f1bbcdcu;
+    } else {
+      f = b ^ c ^ d;
+      k = 0xca62c1d6u;
+    }
+    let t = ROTLEFT(a, 5u) + f + e + k + w[i & 15u];
+    e = d;
+    d = c;
+    c = ROTLEFT(b, 30u);
+    b = a;
+    a = t;
+  }
+
+  (*state)[0] += a;
+  (*state)[1] += b;
+  (*state)[2] += c;
+  (*state)[3] += d;
+  (*state)[4] += e;
+}
+
+fn digest(state : ptr<function, array<u32, STATE_WORDS>>) -> array<u32, DIGEST_WORDS> {
+  return *state;
+}
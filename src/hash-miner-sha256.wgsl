@@ -0,0 +1,88 @@
+// SHA-256 for `hash-miner.wgsl`.
+
+const STATE_WORDS = 8u;
+const BLOCK_WORDS = 16u;
+const DIGEST_WORDS = 8u;
+const BIG_ENDIAN_WORDS = true;
+
+// Hash the digest once more, as Bitcoin does.
+override DOUBLE_SHA256: bool = false;
+
+const IV = array<u32, 8>(
+  0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19
+);
+
+const k = array<u32, 64> (
+  0x428a2f98,0x71374491,0xb5c0fbcf,0xe9b5dba5,0x3956c25b,0x59f111f1,0x923f82a4,0xab1c5ed5,
+  0xd807aa98,0x12835b01,0x243185be,0x550c7dc3,0x72be5d74,0x80deb1fe,0x9bdc06a7,0xc19bf174,
+  0xe49b69c1,0xefbe4786,0x0fc19dc6,0x240ca1cc,0x2de92c6f,0x4a7484aa,0x5cb0a9dc,0x76f988da,
+  0x983e5152,0xa831c66d,0xb00327c8,0xbf597fc7,0xc6e00bf3,0xd5a79147,0x06ca6351,0x14292967,
+  0x27b70a85,0x2e1b2138,0x4d2c6dfc,0x53380d13,0x650a7354,0x766a0abb,0x81c2c92e,0x92722c85,
+  0xa2bfe8a1,0xa81a664b,0xc24b8b70,0xc76c51a3,0xd192e819,0xd6990624,0xf40e3585,0x106aa070,
+  0x19a4c116,0x1e376c08,0x2748774c,0x34b0bcb5,0x391c0cb3,0x4ed8aa4a,0x5b9cca4f,0x682e6ff3,
+  0x748f82ee,0x78a5636f,0x84c87814,0x8cc70208,0x90befffa,0xa4506ceb,0xbef9a3f7,0xc67178f2
+);
+
+fn ROTRIGHT(a : u32, b : u32) -> u32 { return (a >> b) | (a << (32u - b)); }
+
+fn CH(x : u32, y : u32, z : u32) -> u32 { return (x & y) ^ (~x & z); }
+fn MAJ(x : u32, y : u32, z : u32) -> u32 { return (x & y) ^ (x & z) ^ (y & z); }
+fn EP0(x : u32) -> u32 { return ROTRIGHT(x, 2u) ^ ROTRIGHT(x, 13u) ^ ROTRIGHT(x, 22u); }
+fn EP1(x : u32) -> u32 { return ROTRIGHT(x, 6u) ^ ROTRIGHT(x, 11u) ^ ROTRIGHT(x, 25u); }
+fn SIG0(x : u32) -> u32 { return ROTRIGHT(x, 7u) ^ ROTRIGHT(x, 18u) ^ (x >> 3u); }
+fn SIG1(x : u32) -> u32 { return ROTRIGHT(x, 17u) ^ ROTRIGHT(x, 19u) ^ (x >> 10u); }
+
+// The message schedule is kept as a rolling window of 16 words instead of all 64.
+fn compress(state : ptr<function, array<u32, STATE_WORDS>>, block : ptr<function, array<u32, BLOCK_WORDS>>) {
+  var w = *block;
+  var a = (*state)[0];
+  var b = (*state)[1];
+  var c = (*state)[2];
+  var d = (*state)[3];
+  var e = (*state)[4];
+  var f = (*state)[5];
+  var g = (*state)[6];
+  var h = (*state)[7];
+
+  for (var i = 0u; i < 64u; i++) {
+    if (i >= 16u) {
+      w[i & 15u] = SIG1(w[(i - 2u) & 15u]) + w[(i - 7u) & 15u] + SIG0(w[(i - 15u) & 15u])
+        + w[i & 15u];
+    }
+    let t1 = h + EP1(e) + CH(e, f, g) + k[i] + w[i & 15u];
+    let t2 = EP0(a) + MAJ(a, b, c);
+    h = g;
+    g = f;
+    f = e;
+    e = d + t1;
+    d = c;
+    c = b;
+    b = a;
+    a = t1 + t2;
+  }
+
+  (*state)[0] += a;
+  (*state)[1] += b;
+  (*state)[2] += c;
+  (*state)[3] += d;
+  (*state)[4] += e;
+  (*state)[5] += f;
+  (*state)[6] += g;
+  (*state)[7] += h;
+}
+
+fn digest(state : ptr<function, array<u32, STATE_WORDS>>) -> array<u32, DIGEST_WORDS> {
+  if (!DOUBLE_SHA256) {
+    return *state;
+  }
+  // The digest fits in one padded block.
+  var block : array<u32, BLOCK_WORDS>;
+  for (var j = 0u; j < 8u; j++) {
+    block[j] = (*state)[j];
+  }
+  block[8] = 0x80000000u;
+  block[15] = 256u;
+  var hash = IV;
+  compress(&hash, &block);
+  return hash;
+}
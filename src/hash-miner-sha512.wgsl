@@ -0,0 +1,117 @@
+// SHA-512 for `hash-miner.wgsl`. WGSL has no 64-bit integers, so a word is a `vec2<u32>` of its
+// high and low halves, which is also how the state and the blocks hold them.
+
+const STATE_WORDS = 16u;
+const BLOCK_WORDS = 32u;
+const DIGEST_WORDS = 16u;
+const BIG_ENDIAN_WORDS = true;
+
+const k = array<vec2<u32>, 80>(
+  vec2(0x428a2f98u, 0xd728ae22u), vec2(0x71374491u, 0x23ef65cdu), vec2(0xb5c0fbcfu, 0xec4d3b2fu),
+  vec2(0xe9b5dba5u, 0x8189dbbcu), vec2(0x3956c25bu, 0xf348b538u), vec2(0x59f111f1u, 0xb605d019u),
+  vec2(0x923f82a4u, 0xaf194f9bu), vec2(0xab1c5ed5u, 0xda6d8118u), vec2(0xd807aa98u, 0xa3030242u),
+  vec2(0x12835b01u, 0x45706fbeu), vec2(0x243185beu, 0x4ee4b28cu), vec2(0x550c7dc3u, 0xd5ffb4e2u),
+  vec2(0x72be5d74u, 0xf27b896fu), vec2(0x80deb1feu, 0x3b1696b1u), vec2(0x9bdc06a7u, 0x25c71235u),
+  vec2(0xc19bf174u, 0xcf692694u), vec2(0xe49b69c1u, 0x9ef14ad2u), vec2(0xefbe4786u, 0x384f25e3u),
+  vec2(0x0fc19dc6u, 0x8b8cd5b5u), vec2(0x240ca1ccu, 0x77ac9c65u), vec2(0x2de92c6fu, 0x592b0275u),
+  vec2(0x4a7484aau, 0x6ea6e483u), vec2(0x5cb0a9dcu, 0xbd41fbd4u), vec2(0x76f988dau, 0x831153b5u),
+  vec2(0x983e5152u, 0xee66dfabu), vec2(0xa831c66du, 0x2db43210u), vec2(0xb00327c8u, 0x98fb213fu),
+  vec2(0xbf597fc7u, 0xbeef0ee4u), vec2(0xc6e00bf3u, 0x3da88fc2u), vec2(0xd5a79147u, 0x930aa725u),
+  vec2(0x06ca6351u, 0xe003826fu), vec2(0x14292967u, 0x0a0e6e70u), vec2(0x27b70a85u, 0x46d22ffcu),
+  vec2(0x2e1b2138u, 0x5c26c926u), vec2(0x4d2c6dfcu, 0x5ac42aedu), vec2(0x53380d13u, 0x9d95b3dfu),
+  vec2(0x650a7354u, 0x8baf63deu), vec2(0x766a0abbu, 0x3c77b2a8u), vec2(0x81c2c92eu, 0x47edaee6u),
+  vec2(0x92722c85u, 0x1482353bu), vec2(0xa2bfe8a1u, 0x4cf10364u), vec2(0xa81a664bu, 0xbc423001u),
+  vec2(0xc24b8b70u, 0xd0f89791u), vec2(0xc76c51a3u, 0x0654be30u), vec2(0xd192e819u, 0xd6ef5218u),
+  vec2(0xd6990624u, 0x5565a910u), vec2(0xf40e3585u, 0x5771202au), vec2(0x106aa070u, 0x32bbd1b8u),
+  vec2(0x19a4c116u, 0xb8d2d0c8u), vec2(0x1e376c08u, 0x5141ab53u), vec2(0x2748774cu, 0xdf8eeb99u),
+  vec2(0x34b0bcb5u, 0xe19b48a8u), vec2(0x391c0cb3u, 0xc5c95a63u), vec2(0x4ed8aa4au, 0xe3418acbu),
+  vec2(0x5b9cca4fu, 0x7763e373u), vec2(0x682e6ff3u, 0xd6b2b8a3u), vec2(0x748f82eeu, 0x5defb2fcu),
+  vec2(0x78a5636fu, 0x43172f60u), vec2(0x84c87814u, 0xa1f0ab72u), vec2(0x8cc70208u, 0x1a6439ecu),
+  vec2(0x90befffau, 0x23631e28u), vec2(0xa4506cebu, 0xde82bde9u), vec2(0xbef9a3f7u, 0xb2c67915u),
+  vec2(0xc67178f2u, 0xe372532bu), vec2(0xca273eceu, 0xea26619cu), vec2(0xd186b8c7u, 0x21c0c207u),
+  vec2(0xeada7dd6u, 0xcde0eb1eu), vec2(0xf57d4f7fu, 0xee6ed178u), vec2(0x06f067aau, 0x72176fbau),
+  vec2(0x0a637dc5u, 0xa2c898a6u), vec2(0x113f9804u, 0xbef90daeu), vec2(0x1b710b35u, 0x131c471bu),
+  vec2(0x28db77f5u, 0x23047d84u), vec2(0x32caab7bu, 0x40c72493u), vec2(0x3c9ebe0au, 0x15c9bebcu),
+  vec2(0x431d67c4u, 0x9c100d4cu), vec2(0x4cc5d4beu, 0xcb3e42b6u), vec2(0x597f299cu, 0xfc657e2au),
+  vec2(0x5fcb6fabu, 0x3ad6faecu), vec2(0x6c44198cu, 0x4a475817u)
+);
+
+fn add64(a : vec2<u32>, b : vec2<u32>) -> vec2<u32> {
+  let low = a.y + b.y;
+  return vec2(a.x + b.x + select(0u, 1u, low < a.y), low);
+}
+
+// `b` is in 1..64 but not 32.
+fn ROTRIGHT64(a : vec2<u32>, b : u32) -> vec2<u32> {
+  var x = a;
+  var n = b;
+  if (n > 32u) {
+    x = x.yx;
+    n -= 32u;
+  }
+  return vec2((x.x >> n) | (x.y << (32u - n)), (x.y >> n) | (x.x << (32u - n)));
+}
+
+// `b` is in 1..32.
+fn SHIFTRIGHT64(a : vec2<u32>, b : u32) -> vec2<u32> {
+  return vec2(a.x >> b, (a.y >> b) | (a.x << (32u - b)));
+}
+
+fn EP0(x : vec2<u32>) -> vec2<u32> { return ROTRIGHT64(x, 28u) ^ ROTRIGHT64(x, 34u) ^ ROTRIGHT64(x, 39u); }
+fn EP1(x : vec2<u32>) -> vec2<u32> { return ROTRIGHT64(x, 14u) ^ ROTRIGHT64(x, 18u) ^ ROTRIGHT64(x, 41u); }
+fn SIG0(x : vec2<u32>) -> vec2<u32> { return ROTRIGHT64(x, 1u) ^ ROTRIGHT64(x, 8u) ^ SHIFTRIGHT64(x, 7u); }
+fn SIG1(x : vec2<u32>) -> vec2<u32> { return ROTRIGHT64(x, 19u) ^ ROTRIGHT64(x, 61u) ^ SHIFTRIGHT64(x, 6u); }
+
+// The message schedule is kept as a rolling window of 16 words instead of all 80.
+fn compress(state : ptr<function, array<u32, STATE_WORDS>>, block : ptr<function, array<u32, BLOCK_WORDS>>) {
+  var w : array<vec2<u32>, 16>;
+  for (var i = 0u; i < 16u; i++) {
+    w[i] = vec2((*block)[2u * i], (*block)[2u * i + 1u]);
+  }
+  var v : array<vec2<u32>, 8>;
+  for (var i = 0u; i < 8u; i++) {
+    v[i] = vec2((*state)[2u * i], (*state)[2u * i + 1u]);
+  }
+  var a = v[0];
+  var b = v[1];
+  var c = v[2];
+  var d = v[3];
+  var e = v[4];
+  var f = v[5];
+  var g = v[6];
+  var h = v[7];
+
+  for (var i = 0u; i < 80u; i++) {
+    if (i >= 16u) {
+      w[i & 15u] = add64(add64(SIG1(w[(i - 2u) & 15u]), w[(i - 7u) & 15u]),
+        add64(SIG0(w[(i - 15u) & 15u]), w[i & 15u]));
+    }
+    let t1 = add64(add64(add64(h, EP1(e)), add64((e & f) ^ (~e & g), k[i])), w[i & 15u]);
+    let t2 = add64(EP0(a), (a & b) ^ (a & c) ^ (b & c));
+    h = g;
+    g = f;
+    f = e;
+    e = add64(d, t1);
+    d = c;
+    c = b;
+    b = a;
+    a = add64(t1, t2);
+  }
+
+  v[0] = add64(v[0], a);
+  v[1] = add64(v[1], b);
+  v[2] = add64(v[2], c);
+  v[3] = add64(v[3], d);
+  v[4] = add64(v[4], e);
+  v[5] = add64(v[5], f);
+  v[6] = add64(v[6], g);
+  v[7] = add64(v[7], h);
+  for (var i = 0u; i < 8u; i++) {
+    (*state)[2u * i] = v[i].x;
+    (*state)[2u * i + 1u] = v[i].y;
+  }
+}
+
+fn digest(state : ptr<function, array<u32, STATE_WORDS>>) -> array<u32, DIGEST_WORDS> {
+  return *state;
+}
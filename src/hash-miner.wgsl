@@ -0,0 +1,116 @@
+// Packed layout miner: the message, the target and the hashes are 32-bit words in the byte order
+// the hash function reads them, instead of one byte per `u32` as in `sha256-miner.wgsl`. The host
+// appends this to one of the `hash-miner-*.wgsl` files, which define for their hash function:
+//   STATE_WORDS, BLOCK_WORDS, DIGEST_WORDS: sizes of the state, a block and the digest in `u32`s;
+//   BIG_ENDIAN_WORDS: whether the message bytes are packed into words big-endian;
+//   compress(state, block): absorbs one block into the state;
+//   digest(state): the digest as big-endian words, so that they compare like the digest's bytes.
+// They can use `byte_swap` from here.
+
+// Supplied by the host as pipeline constants.
+override WORKGROUP_SIZE: u32;
+override ITERATIONS_PER_THREAD: u32;
+// Threads per dispatch, `WORKGROUP_SIZE * <workgroups dispatched>`.
+override RUNS_PER_DISPATCH: u32;
+// Compare the hash to the target as a little-endian integer, as Bitcoin does.
+override HASH_LITTLE_ENDIAN: bool = false;
+
+struct Input {
+  // State after the message blocks before the one holding the nonce field.
+  midstate : array<u32, STATE_WORDS>,
+  // Nonce field position in `blocks` in bytes, and its width. The field is a little-endian integer.
+  nonce_offset : u32,
+  nonce_width : u32,
+  // Nonces to search in this dispatch, at most `ITERATIONS_PER_THREAD * RUNS_PER_DISPATCH`.
+  count : u32,
+  // A hit's digest is at most this, big-endian.
+  max_hash : array<u32, DIGEST_WORDS>,
+  // Rest of the message with the nonce field at the dispatch start, padded by the host to whole
+  // blocks.
+  blocks : array<u32>,
+};
+
+struct Hit {
+  // Offset added to the nonce field.
+  offset : u32,
+  hash : array<u32, DIGEST_WORDS>,
+};
+
+struct Hits {
+  // Number of hits found, including the ones that didn't fit in `hits`.
+  count : atomic<u32>,
+  // Set when `hits` is full and a hit had to be dropped.
+  overflow : atomic<u32>,
+  hits : array<Hit>,
+};
+
+@group(0) @binding(0) var<storage, read> input : Input;
+@group(0) @binding(1) var<storage, read_write> result : Hits;
+
+fn byte_swap(x : u32) -> u32 {
+  return (x << 24u) | ((x << 8u) & 0x00ff0000u) | ((x >> 8u) & 0x0000ff00u) | (x >> 24u);
+}
+
+// Word `i` of `input.blocks`, with `carry` added to the bytes of the nonce field in it. Words
+// must be fetched in order; `carry` is left with what goes into the next word.
+fn fetch_word(i : u32, carry : ptr<function, u32>) -> u32 {
+  var word = input.blocks[i];
+  let nonce_end = input.nonce_offset + input.nonce_width;
+  if (*carry == 0u || i * 4u + 4u <= input.nonce_offset || i * 4u >= nonce_end) {
+    return word;
+  }
+  for (var j = 0u; j < 4u; j++) {
+    let position = i * 4u + j;
+    if (position >= input.nonce_offset && position < nonce_end) {
+      let shift = select(j * 8u, 24u - j * 8u, BIG_ENDIAN_WORDS);
+      let sum = ((word >> shift) & 255u) + (*carry & 255u);
+      word = (word & ~(255u << shift)) | ((sum & 255u) << shift);
+      *carry = (*carry >> 8u) + (sum >> 8u);
+    }
+  }
+  return word;
+}
+
+fn meets_target(hash : ptr<function, array<u32, DIGEST_WORDS>>) -> bool {
+  for (var i = 0u; i < DIGEST_WORDS; i++) {
+    var word = (*hash)[i];
+    if (HASH_LITTLE_ENDIAN) {
+      word = byte_swap((*hash)[DIGEST_WORDS - 1u - i]);
+    }
+    if (word != input.max_hash[i]) {
+      return word < input.max_hash[i];
+    }
+  }
+  return true;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn main(@builtin(global_invocation_id) global_id : vec3<u32>) {
+  let words = arrayLength(&input.blocks);
+  for (var i = 0u; i < ITERATIONS_PER_THREAD; i++) {
+    let addition = i * RUNS_PER_DISPATCH + global_id.x;
+    if (addition >= input.count) {
+      break;
+    }
+    var state = input.midstate;
+    var block : array<u32, BLOCK_WORDS>;
+    var carry = addition;
+    for (var first = 0u; first < words; first += BLOCK_WORDS) {
+      for (var j = 0u; j < BLOCK_WORDS; j++) {
+        block[j] = fetch_word(first + j, &carry);
+      }
+      compress(&state, &block);
+    }
+
+    var hash = digest(&state);
+    if meets_target(&hash) {
+      let slot = atomicAdd(&result.count, 1u);
+      if slot < arrayLength(&result.hits) {
+        result.hits[slot].offset = addition;
+        result.hits[slot].hash = hash;
+      } else {
+        atomicStore(&result.overflow, 1u);
+      }
+    }
+  }
+}
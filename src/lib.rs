@@ -1,5 +1,12 @@
+use anyhow::anyhow;
+use clap::ValueEnum;
+use serde::Serialize;
 use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 use std::time::{Duration, Instant};
+use wgpu::{Adapter, Backends, Instance, InstanceDescriptor, InstanceFlags, RequestAdapterOptions};
 
 pub fn set_up_logger() {
     unsafe {
@@ -20,6 +27,12 @@ pub struct FpsStat {
     counter: usize,
 }
 
+impl Default for FpsStat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FpsStat {
     pub fn new() -> Self {
         Self {
@@ -37,3 +50,405 @@ impl FpsStat {
         )
     }
 }
+
+/// Keeps every frame time of a run, in milliseconds.
+#[derive(Default)]
+pub struct FrameStats {
+    frame_times: Vec<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FrameSummary {
+    pub frames: usize,
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub avg_fps: f64,
+    /// Average FPS over the slowest 1% of the frames.
+    pub low_1_percent_fps: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HistogramBucket {
+    pub start_ms: f64,
+    pub end_ms: f64,
+    pub count: usize,
+}
+
+impl FrameStats {
+    pub fn record(&mut self, frame_time: Duration) {
+        self.record_ms(frame_time.as_secs_f64() * 1000.0);
+    }
+
+    pub fn record_ms(&mut self, ms: f64) {
+        self.frame_times.push(ms);
+    }
+
+    pub fn frame_times(&self) -> &[f64] {
+        &self.frame_times
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frame_times.is_empty()
+    }
+
+    fn sorted(&self) -> Vec<f64> {
+        let mut sorted = self.frame_times.clone();
+        sorted.sort_by(f64::total_cmp);
+        sorted
+    }
+
+    /// Returns `None` if nothing was recorded.
+    pub fn summary(&self) -> Option<FrameSummary> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        let sorted = self.sorted();
+        // nearest-rank percentile
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+        let avg = sorted.iter().sum::<f64>() / sorted.len() as f64;
+        let slowest = &sorted[sorted.len() - sorted.len().div_ceil(100)..];
+        let slowest_avg = slowest.iter().sum::<f64>() / slowest.len() as f64;
+
+        Some(FrameSummary {
+            frames: sorted.len(),
+            min_ms: sorted[0],
+            avg_ms: avg,
+            max_ms: sorted[sorted.len() - 1],
+            p50_ms: percentile(50.0),
+            p95_ms: percentile(95.0),
+            p99_ms: percentile(99.0),
+            avg_fps: 1000.0 / avg,
+            low_1_percent_fps: 1000.0 / slowest_avg,
+        })
+    }
+
+    /// Splits the range between the fastest and the slowest frame into `buckets` equal parts.
+    pub fn histogram(&self, buckets: usize) -> Vec<HistogramBucket> {
+        let Some(summary) = self.summary() else {
+            return Vec::new();
+        };
+        let width = (summary.max_ms - summary.min_ms) / buckets as f64;
+        let mut histogram = (0..buckets)
+            .map(|i| HistogramBucket {
+                start_ms: summary.min_ms + width * i as f64,
+                end_ms: summary.min_ms + width * (i + 1) as f64,
+                count: 0,
+            })
+            .collect::<Vec<_>>();
+        for &x in &self.frame_times {
+            let index = if width > 0.0 {
+                ((x - summary.min_ms) / width) as usize
+            } else {
+                0
+            };
+            histogram[index.min(buckets - 1)].count += 1;
+        }
+        histogram
+    }
+}
+
+impl FrameSummary {
+    pub fn print(&self) {
+        println!(
+            "  frames: {}, min: {:.3} ms, avg: {:.3} ms, max: {:.3} ms",
+            self.frames, self.min_ms, self.avg_ms, self.max_ms
+        );
+        println!(
+            "  p50: {:.3} ms, p95: {:.3} ms, p99: {:.3} ms",
+            self.p50_ms, self.p95_ms, self.p99_ms
+        );
+        println!(
+            "  avg FPS: {:.2}, 1% low FPS: {:.2}",
+            self.avg_fps, self.low_1_percent_fps
+        );
+    }
+}
+
+pub fn print_histogram(histogram: &[HistogramBucket]) {
+    let max = histogram.iter().map(|x| x.count).max().unwrap_or_default();
+    for bucket in histogram {
+        let bar = (bucket.count * 50).checked_div(max).unwrap_or_default();
+        println!(
+            "  {:>9.3} - {:>9.3} ms | {:<50} {}",
+            bucket.start_ms,
+            bucket.end_ms,
+            "#".repeat(bar),
+            bucket.count
+        );
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+pub enum OutputFormat {
+    Json,
+    /// A header line and one row; nested fields are joined with dots, arrays are left out.
+    Csv,
+}
+
+/// Writes a benchmark report for other tools to ingest.
+pub fn write_report(
+    path: &Path,
+    format: OutputFormat,
+    report: &impl Serialize,
+) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, report)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Csv => {
+            let mut columns = Vec::new();
+            flatten_json("", &serde_json::to_value(report)?, &mut columns);
+            let header = columns.iter().map(|x| csv_field(&x.0)).collect::<Vec<_>>();
+            let row = columns.iter().map(|x| csv_field(&x.1)).collect::<Vec<_>>();
+            writeln!(writer, "{}", header.join(","))?;
+            writeln!(writer, "{}", row.join(","))?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn flatten_json(prefix: &str, value: &serde_json::Value, out: &mut Vec<(String, String)>) {
+    use serde_json::Value;
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten_json(&key, value, out);
+            }
+        }
+        Value::Array(_) => {}
+        Value::Null => out.push((prefix.into(), String::new())),
+        Value::String(x) => out.push((prefix.into(), x.clone())),
+        x => out.push((prefix.into(), x.to_string())),
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
+
+/// Adapter selection options shared by the binaries.
+#[derive(clap::Args, Debug, Clone)]
+pub struct AdapterArgs {
+    /// Print all available adapters and exit.
+    #[arg(long)]
+    pub list_adapters: bool,
+
+    /// Adapter to use: an index from `--list-adapters`, or a case-insensitive substring of its
+    /// name.
+    #[arg(long)]
+    pub adapter: Option<String>,
+
+    /// Comma separated backends to consider (vulkan, dx12, metal, gl). Defaults to `WGPU_BACKEND`,
+    /// or all backends.
+    #[arg(long)]
+    pub backend: Option<String>,
+
+    /// Power preference used when no `--adapter` is given. Defaults to `WGPU_POWER_PREF`.
+    #[arg(long, value_enum)]
+    pub power_preference: Option<PowerPreference>,
+
+    /// Only accept a fallback (software) adapter, e.g. lavapipe or llvmpipe. Ignored with
+    /// `--adapter`.
+    #[arg(long)]
+    pub force_fallback_adapter: bool,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+pub enum PowerPreference {
+    None,
+    Low,
+    High,
+}
+
+impl From<PowerPreference> for wgpu::PowerPreference {
+    fn from(value: PowerPreference) -> Self {
+        match value {
+            PowerPreference::None => wgpu::PowerPreference::None,
+            PowerPreference::Low => wgpu::PowerPreference::LowPower,
+            PowerPreference::High => wgpu::PowerPreference::HighPerformance,
+        }
+    }
+}
+
+impl AdapterArgs {
+    pub fn backends(&self) -> anyhow::Result<Backends> {
+        let Some(list) = &self.backend else {
+            return Ok(Backends::from_env().unwrap_or_default());
+        };
+        let backends = Backends::from_comma_list(list);
+        if backends.is_empty() {
+            return Err(anyhow!("No known backend in `{}`", list));
+        }
+        Ok(backends)
+    }
+
+    pub fn create_instance(&self) -> anyhow::Result<Instance> {
+        Ok(Instance::new(&InstanceDescriptor {
+            backends: self.backends()?,
+            flags: InstanceFlags::from_env_or_default(),
+            memory_budget_thresholds: Default::default(),
+            backend_options: Default::default(),
+        }))
+    }
+
+    /// Picks the adapter requested on the command line. `compatible_surface` is required to be
+    /// supported by it.
+    pub async fn select_adapter(
+        &self,
+        instance: &Instance,
+        compatible_surface: Option<&wgpu::Surface<'_>>,
+    ) -> anyhow::Result<Adapter> {
+        let adapter = match &self.adapter {
+            Some(query) => {
+                let mut adapters = instance.enumerate_adapters(Backends::all()).await;
+                let adapter = adapters.swap_remove(find_adapter(&adapters, query)?);
+                if let Some(surface) = compatible_surface
+                    && !adapter.is_surface_supported(surface)
+                {
+                    return Err(anyhow!(
+                        "Adapter `{}` can't present to the window surface",
+                        adapter.get_info().name
+                    ));
+                }
+                adapter
+            }
+            None => {
+                instance
+                    .request_adapter(&RequestAdapterOptions {
+                        power_preference: self
+                            .power_preference
+                            .map(Into::into)
+                            .or_else(wgpu::PowerPreference::from_env)
+                            .unwrap_or_default(),
+                        force_fallback_adapter: self.force_fallback_adapter,
+                        compatible_surface,
+                    })
+                    .await?
+            }
+        };
+        log::info!("Adapter: {:?}", adapter.get_info());
+        Ok(adapter)
+    }
+
+    /// Picks several adapters: `all`, or a comma separated list of indices or name substrings
+    /// as `--adapter` takes them. Each adapter may only be listed once.
+    pub async fn select_adapters(
+        &self,
+        instance: &Instance,
+        list: &str,
+    ) -> anyhow::Result<Vec<Adapter>> {
+        let adapters = instance.enumerate_adapters(Backends::all()).await;
+        let indices = if list.trim().eq_ignore_ascii_case("all") {
+            (0..adapters.len()).collect::<Vec<_>>()
+        } else {
+            let mut indices = Vec::new();
+            for query in list.split(',').map(str::trim) {
+                let index = find_adapter(&adapters, query)?;
+                if indices.contains(&index) {
+                    return Err(anyhow!("Adapter `{}` is listed twice", query));
+                }
+                indices.push(index);
+            }
+            indices
+        };
+        if indices.is_empty() {
+            return Err(anyhow!("No adapters found"));
+        }
+        let adapters = indices
+            .into_iter()
+            .map(|i| adapters[i].clone())
+            .collect::<Vec<_>>();
+        for adapter in &adapters {
+            log::info!("Adapter: {:?}", adapter.get_info());
+        }
+        Ok(adapters)
+    }
+}
+
+/// Index of the adapter `query` names.
+fn find_adapter(adapters: &[Adapter], query: &str) -> anyhow::Result<usize> {
+    if let Ok(index) = query.parse::<usize>() {
+        if index >= adapters.len() {
+            return Err(anyhow!(
+                "Adapter index {} out of range ({} adapters)",
+                index,
+                adapters.len()
+            ));
+        }
+        return Ok(index);
+    }
+
+    let query = query.to_lowercase();
+    let matches = adapters
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.get_info().name.to_lowercase().contains(&query))
+        .collect::<Vec<_>>();
+    match matches.len() {
+        0 => Err(anyhow!("No adapter name contains `{}`", query)),
+        1 => Ok(matches[0].0),
+        _ => Err(anyhow!(
+            "`{}` matches several adapters: {}",
+            query,
+            matches
+                .iter()
+                .map(|(_, x)| x.get_info().name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Prints every adapter of `instance`, indexed the way `--adapter` expects.
+pub async fn print_adapters(instance: &Instance) {
+    let adapters = instance.enumerate_adapters(Backends::all()).await;
+    if adapters.is_empty() {
+        println!("No adapters found");
+    }
+    for (i, adapter) in adapters.iter().enumerate() {
+        let info = adapter.get_info();
+        println!(
+            "[{}] {} (backend: {:?}, vendor: {} ({:#06x}), type: {:?}, driver: {} {})",
+            i,
+            info.name,
+            info.backend,
+            vendor_name(info.vendor),
+            info.vendor,
+            info.device_type,
+            info.driver,
+            info.driver_info
+        );
+    }
+}
+
+pub fn vendor_name(id: u32) -> &'static str {
+    match id {
+        0x1002 => "AMD",
+        0x106b => "Apple",
+        0x10de => "NVIDIA",
+        0x13b5 => "ARM",
+        0x5143 => "Qualcomm",
+        0x8086 => "Intel",
+        0x10005 => "Mesa",
+        _ => "unknown",
+    }
+}
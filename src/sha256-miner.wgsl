@@ -1,13 +1,12 @@
-fn check_difficulty(buf: ptr<function, array<u32, SHA256_BLOCK_SIZE>>) -> bool { /* stub */; return true; }
-
-// override WORKGROUP_SIZE = 0u;
-// override ITERATIONS_PER_THREAD = 0u;
-// override RUNS_PER_DISPATCH = 0u;
-// override DIFFICULTY_BITS = 0u;
-const WORKGROUP_SIZE = 256u;
-const ITERATIONS_PER_THREAD = 64;
-const RUNS_PER_DISPATCH = 2048u * WORKGROUP_SIZE;
-const DIFFICULTY_BITS = 32u;
+// Supplied by the host as pipeline constants.
+override WORKGROUP_SIZE: u32;
+override ITERATIONS_PER_THREAD: u32;
+// Threads per dispatch, `WORKGROUP_SIZE * <workgroups dispatched>`.
+override RUNS_PER_DISPATCH: u32;
+// Hash the digest once more, as Bitcoin does.
+override DOUBLE_SHA256: bool = false;
+// Compare the hash to the target as a little-endian integer, as Bitcoin does.
+override HASH_LITTLE_ENDIAN: bool = false;
 
 struct SHA256_CTX {
     data : array<u32, 64>,
@@ -17,11 +16,40 @@ struct SHA256_CTX {
     info : u32,
   };
 
-  @group(0) @binding(0) var<storage, read> start : array<u32>;
-  @group(0) @binding(1) var<storage, read_write> result : array<u32>;
+  struct Input {
+    // State after the message blocks before the one holding the nonce field.
+    midstate : array<u32, 8>,
+    // Length of those blocks in bits, low word first.
+    prefix_bitlen : array<u32, 2>,
+    // Nonce field position in `tail`, and its width. The field is a little-endian integer.
+    nonce_offset : u32,
+    nonce_width : u32,
+    // Nonces to search in this dispatch, at most `ITERATIONS_PER_THREAD * RUNS_PER_DISPATCH`.
+    count : u32,
+    // A hit's hash is at most this, big-endian, one byte per `u32`.
+    max_hash : array<u32, SHA256_BLOCK_SIZE>,
+    // Rest of the message, one byte per `u32`, with the nonce field at the dispatch start.
+    tail : array<u32>,
+  };
+
+  struct Hit {
+    // Offset added to the nonce field.
+    offset : u32,
+    hash : array<u32, SHA256_BLOCK_SIZE>,
+  };
+
+  struct Hits {
+    // Number of hits found, including the ones that didn't fit in `hits`.
+    count : atomic<u32>,
+    // Set when `hits` is full and a hit had to be dropped.
+    overflow : atomic<u32>,
+    hits : array<Hit>,
+  };
+
+  @group(0) @binding(0) var<storage, read> input : Input;
+  @group(0) @binding(1) var<storage, read_write> result : Hits;
 
   const SHA256_BLOCK_SIZE = 32;
-  const INPUT_SIZE = 32;
 
   const k = array<u32, 64> (
     0x428a2f98,0x71374491,0xb5c0fbcf,0xe9b5dba5,0x3956c25b,0x59f111f1,0x923f82a4,0xab1c5ed5,
@@ -107,22 +135,36 @@ struct SHA256_CTX {
   }
 
 
-  fn sha256_update(ctx : ptr<function, SHA256_CTX>, input_data: ptr<function, array<u32, INPUT_SIZE>>, len : u32)
+  fn sha256_update_byte(ctx : ptr<function, SHA256_CTX>, byte : u32)
   {
-    for (var i :u32 = 0; i < len; i++) {
-      (*ctx).data[(*ctx).datalen] = input_data[i];
-      (*ctx).datalen++;
-      if ((*ctx).datalen == 64) {
-        sha256_transform(ctx);
-
-        if ((*ctx).bitlen[0] > 0xffffffff - (512)){
-          (*ctx).bitlen[1]++;
-        }
-        (*ctx).bitlen[0] += 512;
+    (*ctx).data[(*ctx).datalen] = byte;
+    (*ctx).datalen++;
+    if ((*ctx).datalen == 64) {
+      sha256_transform(ctx);
+
+      if ((*ctx).bitlen[0] > 0xffffffff - (512)){
+        (*ctx).bitlen[1]++;
+      }
+      (*ctx).bitlen[0] += 512;
 
 
-        (*ctx).datalen = 0;
+      (*ctx).datalen = 0;
+    }
+  }
+
+  // Hashes `input.tail` with `addition` added to its nonce field.
+  fn sha256_update_tail(ctx : ptr<function, SHA256_CTX>, addition : u32)
+  {
+    let nonce_end = input.nonce_offset + input.nonce_width;
+    var carry = addition;
+    for (var i = 0u; i < arrayLength(&input.tail); i++) {
+      var byte = input.tail[i];
+      if (i >= input.nonce_offset && i < nonce_end) {
+        let sum = byte + carry;
+        byte = sum & 255u;
+        carry = sum >> 8u;
       }
+      sha256_update_byte(ctx, byte);
     }
   }
 
@@ -194,35 +236,54 @@ struct SHA256_CTX {
     (*ctx).state[7] = 0x5be0cd19;
   }
 
-fn set_local_input_with_offset(p_data: ptr<function, array<u32, SHA256_BLOCK_SIZE>>, n: u32) {
-    var carry = n;
-
+  fn check_difficulty(buf : ptr<function, array<u32, SHA256_BLOCK_SIZE>>) -> bool {
     for (var i = 0u; i < 32u; i++) {
-        let sum = start[i] + carry;
-        (*p_data)[i] = sum & 255u;
-        carry = sum >> 8u;
+      var byte = (*buf)[i];
+      if (HASH_LITTLE_ENDIAN) {
+        byte = (*buf)[31u - i];
+      }
+      if (byte != input.max_hash[i]) {
+        return byte < input.max_hash[i];
+      }
     }
-}
+    return true;
+  }
+
+  // Resumes from the midstate the host computed over the constant leading blocks.
+  fn sha256_init_midstate(ctx : ptr<function, SHA256_CTX>) {
+    (*ctx).datalen = 0;
+    (*ctx).bitlen[0] = input.prefix_bitlen[0];
+    (*ctx).bitlen[1] = input.prefix_bitlen[1];
+    (*ctx).state = input.midstate;
+  }
 
 @compute @workgroup_size(WORKGROUP_SIZE)
 fn main(@builtin(global_invocation_id) global_id : vec3<u32>) {
-    let _r = result[0];
-
     for (var i = 0u; i < ITERATIONS_PER_THREAD; i += 1) {
       let addition = i * RUNS_PER_DISPATCH + global_id.x;
-      var this_input: array<u32, SHA256_BLOCK_SIZE>;
-
-      set_local_input_with_offset(&this_input, addition);
+      if (addition >= input.count) {
+        break;
+      }
       var ctx : SHA256_CTX;
-      sha256_init(&ctx);
+      sha256_init_midstate(&ctx);
       var buf : array<u32, SHA256_BLOCK_SIZE>;
-      sha256_update(&ctx, &this_input, INPUT_SIZE);
+      sha256_update_tail(&ctx, addition);
       sha256_final(&ctx, &buf);
+      if (DOUBLE_SHA256) {
+        sha256_init(&ctx);
+        for (var j = 0u; j < 32u; j++) {
+          sha256_update_byte(&ctx, buf[j]);
+        }
+        sha256_final(&ctx, &buf);
+      }
 
       if check_difficulty(&buf) {
-        for (var j = 0u; j < 32; j += 1) {
-          // result[j] = buf[j];
-          result[j] = this_input[j];
+        let slot = atomicAdd(&result.count, 1u);
+        if slot < arrayLength(&result.hits) {
+          result.hits[slot].offset = addition;
+          result.hits[slot].hash = buf;
+        } else {
+          atomicStore(&result.overflow, 1u);
         }
       }
     }
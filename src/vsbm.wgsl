@@ -1,5 +1,10 @@
-const KERNEL_ITERATIONS: i32 = 5;
-const CANVAS_SIZE = vec2f(1024, 1024);
+override KERNEL_ITERATIONS: i32 = 5;
+// number of raymarch steps along a ray
+override RAYMARCH_STEPS: i32 = 1000;
+// raymarch step length, relative to the camera distance
+override STEP_SIZE: f32 = 0.002;
+// offset for the normal estimation, relative to the hit distance
+override NORMAL_EPSILON: f32 = 0.00025;
 
 struct Uniforms {
     origin: vec3f,
@@ -17,13 +22,14 @@ struct Uniforms {
 
 var<immediate> ui: Uniforms;
 
-fn pixel_to_ndc(pos: vec2f) -> vec2f {
-    let ndc = (pos / CANVAS_SIZE * 2.0 - 1.0);
-    return vec2f(ndc.x, -ndc.y);
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    // interpolated to the pixel center, independent of the canvas size
+    @location(0) ndc: vec2f,
 }
 
 @vertex
-fn vs_main(@builtin(vertex_index) idx: u32) -> @builtin(position) vec4f {
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOutput {
     let pos = array(
         vec2f(-1, -1),
         vec2f(-1, 1),
@@ -32,7 +38,10 @@ fn vs_main(@builtin(vertex_index) idx: u32) -> @builtin(position) vec4f {
         vec2f(1, -1),
         vec2f(-1, -1),
     );
-    return vec4f(pos[idx], 0.0, 1.0);
+    var out: VertexOutput;
+    out.position = vec4f(pos[idx], 0.0, 1.0);
+    out.ndc = pos[idx];
+    return out;
 }
 
 fn kernel(ver: vec3f) -> f32 {
@@ -49,11 +58,11 @@ fn kernel(ver: vec3f) -> f32 {
 }
 
 @fragment
-fn fs_main(@builtin(position) fs_pos: vec4f) -> @location(0) vec4f {
-    let c = pixel_to_ndc(fs_pos.xy);
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    let c = in.ndc;
     let M_L = 0.381966;
     let M_R = 0.618033;
-    let step_size = 0.002;
+    let step_size = STEP_SIZE;
 
     let dir = ui.forward + ui.right * c.x * ui.screen_size.x + ui.up * c.y * ui.screen_size.y;
     let local_dir = normalize(vec3f(c.x * ui.screen_size.x, c.y * ui.screen_size.y, -1.0));
@@ -63,7 +72,7 @@ fn fs_main(@builtin(position) fs_pos: vec4f) -> @location(0) vec4f {
     var sign = 0;
     var r3: f32 = 0.0;
 
-    for (var k: i32 = 2; k < 1002; k++) {
+    for (var k: i32 = 2; k < RAYMARCH_STEPS + 2; k++) {
         let ver = ui.origin + dir * (step_size * ui.len * f32(k));
         let v = kernel(ver);
 
@@ -112,7 +121,7 @@ fn fs_main(@builtin(position) fs_pos: vec4f) -> @location(0) vec4f {
     if (sign == 1) {
         let hit_pos = ui.origin + dir * r3;
         let r_sq = dot(hit_pos, hit_pos);
-        let eps = r3 * 0.00025;
+        let eps = r3 * NORMAL_EPSILON;
 
         var n: vec3f;
         n.x = kernel(hit_pos - ui.right * eps) - kernel(hit_pos + ui.right * eps);